@@ -1,11 +1,49 @@
 use config::config::read_toml;
-use consensus::members::Members;
+use consensus::{
+    crypto,
+    members::Members,
+    peers::Transport,
+    server::Options,
+    state_machine::HashChain,
+    tls::{self, TlsOptions},
+    wal::SyncPolicy,
+};
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::{collections::HashMap, str::FromStr};
+use std::time::Duration;
+use std::{collections::HashMap, env, path::Path, str::FromStr};
 use tracing_subscriber::fmt;
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() == 4 && args[1] == "keygen" {
+        if let Err(e) = crypto::write_keypair(Path::new(&args[2]), &args[3]) {
+            panic!("keygen err: {}", e)
+        }
+        return;
+    }
+    if args.len() > 3 && args[1] == "mackeygen" {
+        let ids: Vec<usize> = args[3..]
+            .iter()
+            .map(|id| id.parse().expect("node id"))
+            .collect();
+        if let Err(e) = crypto::write_mac_keys(Path::new(&args[2]), &ids) {
+            panic!("mackeygen err: {}", e)
+        }
+        return;
+    }
+    if args.len() > 3 && args[1] == "certgen" {
+        let ids: Vec<usize> = args[3..]
+            .iter()
+            .map(|id| id.parse().expect("node id"))
+            .collect();
+        if let Err(e) = tls::write_certificates(Path::new(&args[2]), &ids) {
+            panic!("certgen err: {}", e)
+        }
+        return;
+    }
+
     let conf = match read_toml(String::from("./config.toml")) {
         Err(e) => panic!("read toml err: {}", e),
         Ok(conf) => conf,
@@ -22,13 +60,82 @@ async fn main() {
         })
         .collect();
 
-    let membership = Arc::new(Members::new(conf.node.id, conf.node.is_leader, &id_list));
+    let mut membership = Members::new(conf.node.id, &id_list);
+    let mut signing_key = None;
+    if let Some(ref keys) = conf.node.keys {
+        signing_key = match crypto::load_signing_key(&keys.private_key) {
+            Err(e) => panic!("load private key err: {}", e),
+            Ok(key) => Some(key),
+        };
+        let public_keys = keys
+            .public_keys
+            .iter()
+            .map(|(key, path)| match crypto::load_verifying_key(path) {
+                Err(e) => panic!("load public key err: {}", e),
+                Ok(public_key) => (key.parse().unwrap(), public_key),
+            })
+            .collect();
+        membership = membership.with_public_keys(public_keys);
+    }
+    if let Some(ref mac_keys) = conf.node.mac_keys {
+        let mac_keys = mac_keys
+            .iter()
+            .map(|(key, path)| match crypto::load_mac_key(path) {
+                Err(e) => panic!("load mac key err: {}", e),
+                Ok(mac_key) => (key.parse().unwrap(), mac_key),
+            })
+            .collect();
+        membership = membership.with_mac_keys(mac_keys);
+    }
+    let membership = Arc::new(membership);
 
     let level = tracing::Level::from_str(&conf.log.level).unwrap();
 
     fmt().with_max_level(level).init();
 
-    if let Err(err) = consensus::server::run(membership.clone(), conf.server.listen_addr).await {
+    let wal_sync = match conf.node.wal_fsync.as_str() {
+        "always" => SyncPolicy::Always,
+        "interval" => SyncPolicy::Interval(Duration::from_millis(conf.node.wal_fsync_interval_ms)),
+        "never" => SyncPolicy::Never,
+        other => panic!("unknown wal_fsync: {}", other),
+    };
+
+    let transport = match conf.node.transport.as_str() {
+        "stream" => Transport::Stream,
+        "unary" => Transport::Unary,
+        other => panic!("unknown transport: {}", other),
+    };
+
+    let options = Options {
+        request_timeout: Duration::from_millis(conf.node.request_timeout_ms),
+        view_change_timeout: Duration::from_millis(conf.node.view_change_timeout_ms),
+        checkpoint_interval: conf.node.checkpoint_interval,
+        signing_key,
+        authenticators: conf.node.mac_keys.is_some(),
+        reply_timeout: Duration::from_millis(conf.node.reply_timeout_ms),
+        batch_size: conf.node.batch_size,
+        batch_bytes: conf.node.batch_bytes,
+        batch_delay: Duration::from_millis(conf.node.batch_delay_ms),
+        window: conf.node.window,
+        data_dir: conf.node.data_dir.map(PathBuf::from),
+        wal_sync,
+        send_queue: conf.node.send_queue,
+        transport,
+        tls: conf.node.tls.map(|tls| TlsOptions {
+            ca: PathBuf::from(tls.ca),
+            cert: PathBuf::from(tls.cert),
+            key: PathBuf::from(tls.key),
+        }),
+    };
+
+    if let Err(err) = consensus::server::run(
+        membership.clone(),
+        conf.server.listen_addr,
+        options,
+        HashChain::default(),
+    )
+    .await
+    {
         panic!("{}", err)
     }
 }
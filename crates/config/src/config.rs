@@ -22,8 +22,97 @@ pub struct Log {
 #[derive(Deserialize, Debug)]
 pub struct Node {
     pub id: usize,
-    pub is_leader: bool,
     pub members: HashMap<String, String>,
+    #[serde(default = "default_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+    #[serde(default = "default_view_change_timeout_ms")]
+    pub view_change_timeout_ms: u64,
+    #[serde(default = "default_checkpoint_interval")]
+    pub checkpoint_interval: u64,
+    #[serde(default = "default_reply_timeout_ms")]
+    pub reply_timeout_ms: u64,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    #[serde(default = "default_batch_bytes")]
+    pub batch_bytes: usize,
+    #[serde(default = "default_batch_delay_ms")]
+    pub batch_delay_ms: u64,
+    #[serde(default = "default_window")]
+    pub window: usize,
+    pub data_dir: Option<String>,
+    #[serde(default = "default_wal_fsync")]
+    pub wal_fsync: String,
+    #[serde(default = "default_wal_fsync_interval_ms")]
+    pub wal_fsync_interval_ms: u64,
+    #[serde(default = "default_send_queue")]
+    pub send_queue: usize,
+    #[serde(default = "default_transport")]
+    pub transport: String,
+    pub keys: Option<Keys>,
+    // node id -> file of the key shared with it
+    pub mac_keys: Option<HashMap<String, String>>,
+    pub tls: Option<Tls>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Keys {
+    pub private_key: String,
+    pub public_keys: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Tls {
+    pub ca: String,
+    pub cert: String,
+    pub key: String,
+}
+
+fn default_request_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_view_change_timeout_ms() -> u64 {
+    4000
+}
+
+fn default_checkpoint_interval() -> u64 {
+    5
+}
+
+fn default_reply_timeout_ms() -> u64 {
+    10000
+}
+
+fn default_batch_size() -> usize {
+    64
+}
+
+fn default_batch_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_batch_delay_ms() -> u64 {
+    10
+}
+
+fn default_window() -> usize {
+    20
+}
+
+fn default_wal_fsync() -> String {
+    String::from("always")
+}
+
+fn default_wal_fsync_interval_ms() -> u64 {
+    100
+}
+
+fn default_send_queue() -> usize {
+    256
+}
+
+fn default_transport() -> String {
+    String::from("stream")
 }
 
 pub fn read_toml(path: String) -> Result<Conf, ConfigError> {
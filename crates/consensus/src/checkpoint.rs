@@ -0,0 +1,40 @@
+use crate::message::{message::Payload, Message, ViewChange};
+use std::collections::HashSet;
+
+/// whether `checkpoints` holds `quorum` matching checkpoint messages for (seq, digest)
+pub fn is_proven(seq: u64, digest: &str, checkpoints: &[Message], quorum: usize) -> bool {
+    let senders: HashSet<u64> = checkpoints
+        .iter()
+        .filter(|m| match m.payload {
+            Some(Payload::Checkpoint(ref cp)) => cp.seq == seq && cp.digest == digest,
+            _ => false,
+        })
+        .map(|m| m.id)
+        .collect();
+    senders.len() >= quorum
+}
+
+/// whether the stable checkpoint claimed by a view-change message carries its proof
+pub fn view_change_proven(view_change: &ViewChange, quorum: usize) -> bool {
+    if view_change.stable_checkpoint == 0 {
+        return true;
+    }
+    let digest = view_change
+        .checkpoints
+        .iter()
+        .find_map(|m| match m.payload {
+            Some(Payload::Checkpoint(ref cp)) if cp.seq == view_change.stable_checkpoint => {
+                Some(cp.digest.clone())
+            }
+            _ => None,
+        });
+    match digest {
+        Some(digest) => is_proven(
+            view_change.stable_checkpoint,
+            &digest,
+            &view_change.checkpoints,
+            quorum,
+        ),
+        None => false,
+    }
+}
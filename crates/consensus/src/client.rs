@@ -1,34 +1,149 @@
 use crate::{
     error::ConsensusError,
-    message::{pbft_client::PbftClient, Message},
+    message::{
+        message::Payload, pbft_client::PbftClient, Message, MessageResponse, Reply, Request,
+    },
+    quorum, tls,
 };
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::JoinSet;
 use tonic::transport::Endpoint;
 use tracing::{debug, warn};
 
-pub async fn send(addr: &str, msg: Message) -> Result<(), ConsensusError> {
-    let address: Endpoint = addr.parse()?;
-
+pub(crate) async fn send(
+    address: Endpoint,
+    msg: Message,
+) -> Result<MessageResponse, ConsensusError> {
     let mut client = PbftClient::connect(address).await?;
 
     let request = tonic::Request::new(msg);
 
-    let _resp = client.send_message(request).await?;
+    let resp = client.send_message(request).await?;
+
+    Ok(resp.into_inner())
+}
 
-    Ok(())
+/// client of a pbft cluster
+pub struct Client {
+    id: u64,
+    members: HashMap<usize, String>,
+    // timestamp of the last request, replicas drop requests older than the last executed one
+    timestamp: u64,
+    // the ca of the cluster, replicas are verified against it
+    ca: Option<PathBuf>,
 }
 
-pub async fn broadcast(local: usize, list: HashMap<usize, String>, msg: Message) {
-    for (id, addr) in list {
-        if id != local {
-            match send(&addr, msg.clone()).await {
-                Ok(_) => {
-                    debug!("send msg to addr {} success", addr);
-                }
-                Err(err) => {
-                    warn!("send msg to addr {} err: {}", addr, err);
-                }
+impl Client {
+    pub fn new(id: u64, members: HashMap<usize, String>) -> Self {
+        Self {
+            id,
+            members,
+            timestamp: 0,
+            ca: None,
+        }
+    }
+
+    /// talk to replicas serving tls with certificates signed by `ca`. the client presents
+    /// none, so it cannot pass for a replica
+    pub fn with_tls(mut self, ca: PathBuf) -> Self {
+        self.ca = Some(ca);
+        self
+    }
+
+    fn endpoint(&self, id: usize, addr: &str) -> Result<Endpoint, ConsensusError> {
+        let endpoint: Endpoint = addr.parse()?;
+        match self.ca {
+            Some(ref ca) => Ok(endpoint.tls_config(tls::anonymous_config(ca, id)?)?),
+            None => Ok(endpoint),
+        }
+    }
+
+    /// microseconds since the epoch, strictly increasing even if the clock goes back
+    fn next_timestamp(&mut self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+        self.timestamp = now.max(self.timestamp + 1);
+        self.timestamp
+    }
+
+    /// send a request to every replica and return its result once f+1 replicas replied the same.
+    /// requests of a client are executed one at a time, replicas skip older ones
+    pub async fn invoke(&mut self, payload: Vec<u8>) -> Result<Vec<u8>, ConsensusError> {
+        let msg = Message {
+            view: 0,
+            seq: 0,
+            id: self.id,
+            digest: String::new(),
+            payload: Some(Payload::Request(Request {
+                payload,
+                client: self.id,
+                timestamp: self.next_timestamp(),
+            })),
+        };
+
+        let mut tasks = JoinSet::new();
+        for (id, addr) in self.members.iter() {
+            let id = *id;
+            let endpoint = self.endpoint(id, addr);
+            let addr = addr.clone();
+            let msg = msg.clone();
+            tasks.spawn(async move {
+                let resp = match endpoint {
+                    Ok(endpoint) => send(endpoint, msg).await,
+                    Err(err) => Err(err),
+                };
+                (id, resp, addr)
+            });
+        }
+
+        let quorum = quorum::reply_quorum(self.members.len());
+        // replies keyed by the member that was contacted, not the replica id they claim
+        let mut replies: Vec<(usize, Reply)> = Vec::new();
+        let mut busy = 0;
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok((id, Ok(resp), addr)) => match resp.reply {
+                    Some(reply) => {
+                        debug!("reply from node{} addr {}", id, addr);
+                        replies.push((id, reply));
+                    }
+                    None if resp.busy => {
+                        debug!("node{} addr {} busy", id, addr);
+                        busy += 1;
+                    }
+                    None => warn!("no reply from addr {}: {}", addr, resp.message),
+                },
+                Ok((_, Err(err), addr)) => warn!("send request to addr {} err: {}", addr, err),
+                Err(err) => warn!("send request task err: {}", err),
+            }
+            if let Some(result) = accept(&replies, quorum) {
+                return Ok(result);
             }
         }
+        // the window of the primary is full, the caller should back off rather than resend
+        if busy > 0 {
+            return Err(ConsensusError::Busy());
+        }
+        Err(ConsensusError::NotEnoughReplies(replies.len()))
+    }
+}
+
+/// the result returned by at least `quorum` different members
+pub(crate) fn accept(replies: &[(usize, Reply)], quorum: usize) -> Option<Vec<u8>> {
+    let mut votes: HashMap<(u64, &[u8]), Vec<usize>> = HashMap::new();
+    for (member, reply) in replies {
+        let replicas = votes
+            .entry((reply.timestamp, reply.result.as_slice()))
+            .or_default();
+        if !replicas.contains(member) {
+            replicas.push(*member);
+        }
+        if replicas.len() >= quorum {
+            return Some(reply.result.clone());
+        }
     }
+    None
 }
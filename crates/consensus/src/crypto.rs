@@ -0,0 +1,192 @@
+use crate::error::ConsensusError;
+use crate::message::{message::Payload, Message};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use hmac::{Hmac, Mac};
+use prost::Message as _;
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// symmetric key shared by a pair of replicas
+pub type MacKey = [u8; 32];
+
+/// generate a new ed25519 key pair
+pub fn generate_keypair() -> (SigningKey, VerifyingKey) {
+    let signing_key = SigningKey::generate(&mut OsRng);
+    let verifying_key = signing_key.verifying_key();
+    (signing_key, verifying_key)
+}
+
+/// write a key pair as hex encoded `<name>.key` and `<name>.pub` files under `dir`
+pub fn write_keypair(dir: &Path, name: &str) -> Result<(), ConsensusError> {
+    let (signing_key, verifying_key) = generate_keypair();
+    fs::create_dir_all(dir)?;
+    fs::write(
+        dir.join(format!("{}.key", name)),
+        hex::encode(signing_key.to_bytes()),
+    )?;
+    fs::write(
+        dir.join(format!("{}.pub", name)),
+        hex::encode(verifying_key.to_bytes()),
+    )?;
+    Ok(())
+}
+
+/// write a key for every pair of `ids` as hex encoded `mac-<i>-<j>.key` files under `dir`, i < j
+pub fn write_mac_keys(dir: &Path, ids: &[usize]) -> Result<(), ConsensusError> {
+    fs::create_dir_all(dir)?;
+    for (n, i) in ids.iter().enumerate() {
+        for j in &ids[n + 1..] {
+            let mut key: MacKey = [0u8; 32];
+            OsRng.fill_bytes(&mut key);
+            fs::write(
+                dir.join(format!("mac-{}-{}.key", i.min(j), i.max(j))),
+                hex::encode(key),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub fn load_mac_key(path: &str) -> Result<MacKey, ConsensusError> {
+    read_key(path)
+}
+
+pub fn load_signing_key(path: &str) -> Result<SigningKey, ConsensusError> {
+    Ok(SigningKey::from_bytes(&read_key(path)?))
+}
+
+pub fn load_verifying_key(path: &str) -> Result<VerifyingKey, ConsensusError> {
+    VerifyingKey::from_bytes(&read_key(path)?)
+        .map_err(|e| ConsensusError::InvalidKey(format!("{}: {}", path, e)))
+}
+
+fn read_key(path: &str) -> Result<[u8; 32], ConsensusError> {
+    let content = fs::read_to_string(path)?;
+    let bytes = hex::decode(content.trim())
+        .map_err(|e| ConsensusError::InvalidKey(format!("{}: {}", path, e)))?;
+    bytes
+        .try_into()
+        .map_err(|_| ConsensusError::InvalidKey(format!("{}: expect 32 bytes", path)))
+}
+
+/// sign the canonical bytes of `m` and store the signature in its payload
+pub fn sign(m: &mut Message, key: &SigningKey) {
+    let signature = key.sign(&signing_bytes(m)).to_bytes().to_vec();
+    if let Some(sig) = signature_mut(m) {
+        *sig = signature;
+    }
+}
+
+/// verify the signature carried in the payload of `m`
+pub fn verify(m: &Message, key: &VerifyingKey) -> bool {
+    let Some(sig) = signature(m) else {
+        return false;
+    };
+    let Ok(sig) = Signature::from_slice(sig) else {
+        return false;
+    };
+    key.verify(&signing_bytes(m), &sig).is_ok()
+}
+
+/// pre-prepare, prepare and commit can be authenticated with macs instead of a signature
+pub fn has_authenticator(m: &Message) -> bool {
+    authenticator(m).is_some()
+}
+
+/// fill the authenticator of `m` with a mac for every replica in `keys`
+pub fn authenticate(m: &mut Message, keys: &HashMap<usize, MacKey>) {
+    let bytes = signing_bytes(m);
+    let macs = keys
+        .iter()
+        .map(|(id, key)| {
+            (
+                *id as u64,
+                mac(key, &bytes).finalize().into_bytes().to_vec(),
+            )
+        })
+        .collect();
+    if let Some(authenticator) = authenticator_mut(m) {
+        *authenticator = macs;
+    }
+}
+
+/// verify the mac for replica `local` in the authenticator of `m`
+pub fn verify_mac(m: &Message, local: usize, key: &MacKey) -> bool {
+    let Some(tag) = authenticator(m).and_then(|a| a.get(&(local as u64))) else {
+        return false;
+    };
+    mac(key, &signing_bytes(m)).verify_slice(tag).is_ok()
+}
+
+fn mac(key: &MacKey, bytes: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("hmac takes keys of any length");
+    mac.update(bytes);
+    mac
+}
+
+/// a copy of `m` with an empty signature and authenticator
+pub fn unsigned(m: &Message) -> Message {
+    let mut m = m.clone();
+    if let Some(sig) = signature_mut(&mut m) {
+        sig.clear();
+    }
+    if let Some(authenticator) = authenticator_mut(&mut m) {
+        authenticator.clear();
+    }
+    m
+}
+
+fn signing_bytes(m: &Message) -> Vec<u8> {
+    unsigned(m).encode_to_vec()
+}
+
+fn authenticator(m: &Message) -> Option<&BTreeMap<u64, Vec<u8>>> {
+    match m.payload.as_ref()? {
+        Payload::PrePrepare(p) => Some(&p.authenticator),
+        Payload::Prepare(p) => Some(&p.authenticator),
+        Payload::Commit(p) => Some(&p.authenticator),
+        _ => None,
+    }
+}
+
+fn authenticator_mut(m: &mut Message) -> Option<&mut BTreeMap<u64, Vec<u8>>> {
+    match m.payload.as_mut()? {
+        Payload::PrePrepare(p) => Some(&mut p.authenticator),
+        Payload::Prepare(p) => Some(&mut p.authenticator),
+        Payload::Commit(p) => Some(&mut p.authenticator),
+        _ => None,
+    }
+}
+
+fn signature(m: &Message) -> Option<&Vec<u8>> {
+    match m.payload.as_ref()? {
+        Payload::PrePrepare(p) => Some(&p.signature),
+        Payload::Prepare(p) => Some(&p.signature),
+        Payload::Commit(p) => Some(&p.signature),
+        Payload::ViewChange(p) => Some(&p.signature),
+        Payload::ViewChangeAck(p) => Some(&p.signature),
+        Payload::NewView(p) => Some(&p.signature),
+        Payload::Checkpoint(p) => Some(&p.signature),
+        Payload::Request(_) => None,
+    }
+}
+
+fn signature_mut(m: &mut Message) -> Option<&mut Vec<u8>> {
+    match m.payload.as_mut()? {
+        Payload::PrePrepare(p) => Some(&mut p.signature),
+        Payload::Prepare(p) => Some(&mut p.signature),
+        Payload::Commit(p) => Some(&mut p.signature),
+        Payload::ViewChange(p) => Some(&mut p.signature),
+        Payload::ViewChangeAck(p) => Some(&mut p.signature),
+        Payload::NewView(p) => Some(&mut p.signature),
+        Payload::Checkpoint(p) => Some(&mut p.signature),
+        Payload::Request(_) => None,
+    }
+}
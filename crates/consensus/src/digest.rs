@@ -0,0 +1,36 @@
+use crate::message::{Reply, Request};
+use prost::Message as _;
+use sha2::{Digest, Sha256};
+
+/// SHA-256 digest of the encoded request, hex encoded
+pub fn request_digest(request: &Request) -> String {
+    format!("{:x}", Sha256::digest(request.encode_to_vec()))
+}
+
+/// digest of the batch ordered by a pre-prepare, an empty batch is the null request
+pub fn batch_digest(requests: &[Request]) -> String {
+    let mut hasher = Sha256::new();
+    for request in requests {
+        hasher.update(request.encode_length_delimited_to_vec());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// digest of a checkpoint, covering the state and the last reply to every client,
+/// which decides whether a retransmitted request is executed again
+pub fn checkpoint_digest(state: &str, replies: &[Reply]) -> String {
+    let mut replies: Vec<&Reply> = replies.iter().collect();
+    replies.sort_unstable_by_key(|reply| reply.client);
+    let mut hasher = Sha256::new();
+    hasher.update(state.as_bytes());
+    for reply in replies {
+        // the view and the replica differ between the replicas that executed the request
+        let reply = Reply {
+            view: 0,
+            replica: 0,
+            ..reply.clone()
+        };
+        hasher.update(reply.encode_length_delimited_to_vec());
+    }
+    format!("{:x}", hasher.finalize())
+}
@@ -12,11 +12,55 @@ pub enum ConsensusError {
     #[error("mutex lock err")]
     MutexError(),
     #[error("status is :{0}")]
-    RPCError(#[from] tonic::Status),
+    RPCError(Box<tonic::Status>),
     #[error("tonic transport err is :{0}")]
     TransportError(#[from] tonic::transport::Error),
     #[error("parse addr err is :{0}")]
     ParseAddrError(#[from] AddrParseError),
     #[error("no such message type")]
     NoSuchMessageType(),
+    #[error("cluster of {0} members cannot tolerate a faulty node, at least 4 are required")]
+    ClusterTooSmall(usize),
+    #[error("node{0} is already a member")]
+    MemberExists(usize),
+    #[error("node{0} is not a member")]
+    NotAMember(usize),
+    #[error("request not executed in time")]
+    ReplyTimeout(),
+    #[error("too many requests in flight, retry later")]
+    Busy(),
+    #[error("window of {0} sequences cannot hold a checkpoint interval of {1}")]
+    WindowTooSmall(usize, u64),
+    #[error("request is older than the last executed one at timestamp {0}")]
+    StaleRequest(u64),
+    #[error("only {0} matching replies, not enough")]
+    NotEnoughReplies(usize),
+    #[error("state machine err: {0}")]
+    StateMachineError(String),
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+    #[error("decode err: {0}")]
+    DecodeError(#[from] prost::DecodeError),
+    #[error("node{0} is unreachable, retry after backoff")]
+    PeerUnavailable(usize),
+    #[error("node{0} is not the identity of the peer certificate")]
+    UnauthenticatedPeer(u64),
+    #[error("state transfer needs the certificate of a replica")]
+    UnauthenticatedReplica(),
+    #[error("mac authenticators need a signing key for view changes and checkpoints")]
+    MacWithoutSigningKey(),
+    #[error("certificate err: {0}")]
+    CertificateError(String),
+    #[error("state transfer err: {0}")]
+    InvalidStateTransfer(String),
+    #[error("snapshot at sequence {0} does not match its digest")]
+    SnapshotMismatch(u64),
+    #[error("io err: {0}")]
+    IOError(#[from] std::io::Error),
+}
+
+impl From<tonic::Status> for ConsensusError {
+    fn from(status: tonic::Status) -> Self {
+        ConsensusError::RPCError(Box::new(status))
+    }
 }
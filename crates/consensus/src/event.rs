@@ -1,16 +1,31 @@
+use crate::crypto;
+use crate::digest::{checkpoint_digest, request_digest};
+use crate::error::ConsensusError;
 use crate::members::Membership;
 use crate::message::message::Payload;
-use crate::message::{Commit, PrePrepare, Prepare};
-use crate::{client::broadcast, message::Message};
+use crate::message::{Checkpoint, Commit, Prepare, Reply, Request, Snapshot};
+use crate::reply::Replies;
+use crate::server::Options;
+use crate::snapshot;
+use crate::state_machine::StateMachine;
+use crate::transfer::{self, Store, Transferred};
+use crate::{message::Message, peers::Peers};
+use ed25519_dalek::SigningKey;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::mpsc::Receiver;
-use tracing::{debug, info};
+use tokio::{
+    select,
+    sync::mpsc::{self, Receiver, UnboundedSender},
+};
+use tracing::{debug, error, info, warn};
 
 pub enum EventType {
     Broadcast = 0,
     Commit = 1,
-    Commited = 2,
+    Stable = 2,
 }
 
 pub struct Event {
@@ -21,18 +36,8 @@ pub struct Event {
 impl Event {
     pub fn new_broadcast(node_id: u64, m: Message) -> Self {
         let msg = match m.payload {
-            Some(Payload::Request(request)) => Some(Payload::PrePrepare(PrePrepare {
-                payload: request.payload,
-                signature: vec![],
-            })),
-            Some(Payload::PrePrepare(pre_prepare)) => Some(Payload::Prepare(Prepare {
-                payload: pre_prepare.payload,
-                signature: vec![],
-            })),
-            Some(Payload::Prepare(prepare)) => Some(Payload::Commit(Commit {
-                payload: prepare.payload,
-                signature: vec![],
-            })),
+            Some(Payload::PrePrepare(_)) => Some(Payload::Prepare(Prepare::default())),
+            Some(Payload::Prepare(_)) => Some(Payload::Commit(Commit::default())),
             _ => None,
         };
 
@@ -41,13 +46,20 @@ impl Event {
                 view: m.view,
                 seq: m.seq,
                 id: node_id,
-                digest: String::new(),
+                digest: m.digest,
                 payload: msg,
             },
             event_type: EventType::Broadcast,
         }
     }
 
+    pub fn new_broadcast_message(msg: Message) -> Self {
+        Self {
+            msg,
+            event_type: EventType::Broadcast,
+        }
+    }
+
     pub fn new_commit(msg: Message) -> Self {
         Self {
             msg,
@@ -55,52 +67,308 @@ impl Event {
         }
     }
 
-    // pub fn new_commited(seq: u64) -> Self {
-    //     let mut msg: Message = Default::default();
-    //     msg.seq = seq;
-    //     Self {
-    //         msg,
-    //         event_type: EventType::Commited,
-    //     }
-    // }
+    pub fn new_stable(msg: Message) -> Self {
+        Self {
+            msg,
+            event_type: EventType::Stable,
+        }
+    }
 }
 
-pub struct EventHandler<T: Membership> {
+pub struct EventHandler<T: Membership, S: StateMachine> {
     members: Arc<T>,
     commited_seq: AtomicUsize,
+    // committed-local requests waiting for the ones before them
+    pending: BTreeMap<u64, Message>,
+    state_machine: S,
+    replies: Arc<Replies>,
+    store: Arc<Store>,
+    peers: Arc<Peers>,
+    checkpoint_interval: u64,
+    signing_key: Option<SigningKey>,
+    authenticators: bool,
+    // snapshots of the state machine are persisted here at every checkpoint
+    data_dir: Option<PathBuf>,
     receiver: Receiver<Event>,
+    // checkpoints go to the pool on their own unbounded channel, the pool may be waiting
+    // for room in `receiver` meanwhile
+    checkpoint_sender: UnboundedSender<Message>,
 }
 
-impl<T: Membership> EventHandler<T> {
-    pub fn new(members: Arc<T>, receiver: Receiver<Event>) -> Self {
+impl<T: Membership, S: StateMachine> EventHandler<T, S> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        members: Arc<T>,
+        receiver: Receiver<Event>,
+        checkpoint_sender: UnboundedSender<Message>,
+        options: &Options,
+        state_machine: S,
+        replies: Arc<Replies>,
+        store: Arc<Store>,
+        peers: Arc<Peers>,
+    ) -> Self {
         Self {
             members,
             commited_seq: AtomicUsize::new(0),
+            pending: BTreeMap::new(),
+            state_machine,
+            replies,
+            store,
+            peers,
+            checkpoint_interval: options.checkpoint_interval,
+            signing_key: options.signing_key.clone(),
+            authenticators: options.authenticators,
+            data_dir: options.data_dir.clone(),
             receiver,
+            checkpoint_sender,
         }
     }
 
+    /// resume from a persisted snapshot, requests up to its sequence are not executed again
+    pub async fn restore(&mut self, snapshot: Snapshot) -> Result<(), ConsensusError> {
+        // the state is only replaced by a snapshot matching its digest
+        if !transfer::snapshot_matches::<S>(&snapshot) {
+            return Err(ConsensusError::SnapshotMismatch(snapshot.seq));
+        }
+        self.state_machine.restore(&snapshot.state)?;
+        // cached replies are sent as this replica's own
+        let local = self.members.local_id() as u64;
+        let replies = snapshot
+            .replies
+            .into_iter()
+            .map(|reply| Reply {
+                replica: local,
+                ..reply
+            })
+            .collect();
+        self.replies.restore(replies).await;
+        self.commited_seq
+            .store(snapshot.seq as usize, Ordering::SeqCst);
+        self.pending.retain(|seq, _| *seq > snapshot.seq);
+        info!(
+            "[RECOVERY] restored snapshot. sequence:{} digest:{}",
+            snapshot.seq, snapshot.digest
+        );
+        Ok(())
+    }
+
+    /// fetch a proven checkpoint at `seq` or later from the others, install it
+    /// and execute what they committed after it
+    pub async fn transfer(&mut self, seq: u64) {
+        if let Some(transferred) = self.fetch(seq).await {
+            self.install(transferred).await;
+        }
+    }
+
+    fn fetch(&self, seq: u64) -> impl Future<Output = Option<Transferred>> + Send + 'static {
+        let members = self.members.clone();
+        let peers = self.peers.clone();
+        let commited = self.commited_seq();
+        let verify = self.signing_key.is_some();
+        // commits carry authenticators instead of signatures in mac mode
+        let verify_commits = verify || self.authenticators;
+        warn!(
+            "[TRANSFER] executed up to sequence:{}, fetch stable checkpoint:{}",
+            commited, seq
+        );
+        async move {
+            let fetched =
+                transfer::fetch::<T, S>(&peers, &*members, seq, commited, verify, verify_commits)
+                    .await;
+            if fetched.is_none() {
+                error!("[TRANSFER] no member served checkpoint:{}", seq);
+            }
+            fetched
+        }
+    }
+
+    async fn install(&mut self, transferred: Transferred) {
+        let id = transferred.node;
+        if let Some(snapshot) = transferred.snapshot {
+            if snapshot.seq > self.commited_seq() {
+                if let Err(err) = self.restore(snapshot.clone()).await {
+                    warn!("[TRANSFER] install checkpoint from node{} err: {}", id, err);
+                    return;
+                }
+                // the log was compacted past the snapshot on disk, a restart resumes from this one
+                self.save_snapshot(&snapshot);
+                self.store.snapshot(snapshot).await;
+            }
+        }
+        for m in transferred.committed {
+            self.commit(m).await;
+        }
+        info!(
+            "[TRANSFER] caught up to sequence:{} from node{}",
+            self.commited_seq(),
+            id
+        );
+    }
+
+    pub fn commited_seq(&self) -> u64 {
+        self.commited_seq.load(Ordering::SeqCst) as u64
+    }
+
     pub async fn start(&mut self) {
-        while let Some(event) = self.receiver.recv().await {
-            match event.event_type {
-                EventType::Broadcast => {
-                    broadcast(self.members.local_id(), self.members.members(), event.msg).await;
+        let (tx_transfer, mut rv_transfer) = mpsc::channel(1);
+        // a transfer runs on its own task, the executor keeps handling events meanwhile
+        let mut transferring = false;
+        loop {
+            select! {
+                event = self.receiver.recv() => {
+                    let Some(event) = event else {
+                        break;
+                    };
+                    match event.event_type {
+                        EventType::Broadcast => {
+                            self.peers
+                                .broadcast(self.members.local_id(), self.members.members(), event.msg)
+                                .await;
+                        }
+                        EventType::Commit => {
+                            self.commit(event.msg).await;
+                        }
+                        EventType::Stable => {
+                            if event.msg.seq > self.commited_seq() && !transferring {
+                                transferring = true;
+                                let fetch = self.fetch(event.msg.seq);
+                                let tx = tx_transfer.clone();
+                                tokio::spawn(async move {
+                                    let _ = tx.send(fetch.await).await;
+                                });
+                            }
+                        }
+                    }
                 }
-                EventType::Commit => {
-                    if event.msg.seq == (self.commited_seq.load(Ordering::SeqCst) + 1) as u64 {
-                        info!("[COMMITED] view:{} seq:{}", event.msg.view, event.msg.seq);
-                        self.commited_seq.fetch_add(1, Ordering::SeqCst);
-                    } else {
-                        debug!(
-                            "[COMMIT]view:{} seq:{} > commited_seq + 1:{}. wait",
-                            event.msg.view,
-                            event.msg.seq,
-                            self.commited_seq.load(Ordering::SeqCst) + 1
-                        );
+                Some(transferred) = rv_transfer.recv() => {
+                    transferring = false;
+                    if let Some(transferred) = transferred {
+                        self.install(transferred).await;
                     }
                 }
-                EventType::Commited => {}
             }
         }
     }
+
+    /// buffer a committed-local request and execute every request whose turn has come
+    async fn commit(&mut self, m: Message) {
+        let commited = self.commited_seq.load(Ordering::SeqCst) as u64;
+        if m.seq <= commited {
+            debug!("[COMMIT] view:{} seq:{} already executed", m.view, m.seq);
+            return;
+        }
+        if m.seq > commited + 1 {
+            debug!(
+                "[COMMIT] view:{} seq:{} > commited_seq + 1:{}. wait",
+                m.view,
+                m.seq,
+                commited + 1
+            );
+        }
+        self.pending.entry(m.seq).or_insert(m);
+
+        while let Some(m) = self
+            .pending
+            .remove(&(self.commited_seq.load(Ordering::SeqCst) as u64 + 1))
+        {
+            info!("[COMMITED] view:{} seq:{}", m.view, m.seq);
+            self.execute(&m).await;
+            self.commited_seq.fetch_add(1, Ordering::SeqCst);
+            self.checkpoint(&m).await;
+        }
+    }
+
+    /// execute every request of the batch and reply to each of them
+    async fn execute(&mut self, m: &Message) {
+        let requests = match m.payload {
+            Some(Payload::PrePrepare(ref pre_prepare)) => pre_prepare.requests.as_slice(),
+            _ => &[],
+        };
+        if requests.is_empty() {
+            debug!("[EXECUTE] seq:{} null request", m.seq);
+            return;
+        }
+        for request in requests {
+            self.execute_request(m, request).await;
+        }
+    }
+
+    async fn execute_request(&mut self, m: &Message, request: &Request) {
+        let digest = request_digest(request);
+        // a retransmitted request may be ordered twice, it is executed only once
+        if let Some(last) = self.replies.last(request.client).await {
+            if request.timestamp <= last.timestamp {
+                debug!(
+                    "[EXECUTE] seq:{} client{} timestamp:{} already executed",
+                    m.seq, request.client, request.timestamp
+                );
+                if request.timestamp == last.timestamp {
+                    self.replies.notify(&digest, last).await;
+                }
+                return;
+            }
+        }
+        let result = self.state_machine.execute(m.seq, &request.payload);
+        debug!(
+            "[EXECUTE] seq:{} client{} result:{} bytes",
+            m.seq,
+            request.client,
+            result.len()
+        );
+        let reply = Reply {
+            view: m.view,
+            timestamp: request.timestamp,
+            client: request.client,
+            replica: self.members.local_id() as u64,
+            result,
+        };
+        self.replies.record(reply.clone()).await;
+        self.replies.notify(&digest, reply).await;
+    }
+
+    fn save_snapshot(&self, snapshot: &Snapshot) {
+        if let Some(ref dir) = self.data_dir {
+            if let Err(err) = snapshot::save(dir, snapshot) {
+                error!("save snapshot at sequence:{} err: {}", snapshot.seq, err);
+            }
+        }
+    }
+
+    async fn checkpoint(&mut self, m: &Message) {
+        if self.checkpoint_interval == 0 || m.seq % self.checkpoint_interval != 0 {
+            return;
+        }
+        let replies = self.replies.all().await;
+        let digest = checkpoint_digest(&self.state_machine.digest(), &replies);
+        info!(
+            "[CHECKPOINT] broadcast checkpoint. sequence:{} digest:{}",
+            m.seq, digest
+        );
+        let snapshot = Snapshot {
+            seq: m.seq,
+            digest: digest.clone(),
+            state: self.state_machine.snapshot(),
+            replies,
+        };
+        self.save_snapshot(&snapshot);
+        self.store.snapshot(snapshot).await;
+        let mut cp = Message {
+            view: m.view,
+            seq: m.seq,
+            id: self.members.local_id() as u64,
+            digest: digest.clone(),
+            payload: Some(Payload::Checkpoint(Checkpoint {
+                seq: m.seq,
+                digest,
+                signature: vec![],
+            })),
+        };
+        if let Some(ref key) = self.signing_key {
+            crypto::sign(&mut cp, key);
+        }
+        // the pool logs the checkpoint before broadcasting it
+        if let Err(err) = self.checkpoint_sender.send(cp) {
+            error!("send checkpoint to pool err: {}", err);
+        }
+    }
 }
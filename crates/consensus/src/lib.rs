@@ -1,36 +1,1386 @@
-mod client;
+mod checkpoint;
+pub mod client;
+pub mod crypto;
+mod digest;
 pub mod error;
 mod event;
 pub mod members;
+#[allow(clippy::module_inception)]
 mod message;
+pub mod metrics;
+pub mod peers;
 mod pool;
+pub mod quorum;
+mod recovery;
+mod reply;
 pub mod server;
+mod snapshot;
+pub mod state_machine;
+pub mod tls;
+mod transfer;
+mod view_change;
+pub mod wal;
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        client::send,
-        message::{message::Payload, Message, Request},
+        client::{accept, send},
+        crypto,
+        digest::{batch_digest, checkpoint_digest, request_digest},
+        error::ConsensusError,
+        event::{Event, EventHandler, EventType},
+        members::{Members, Membership},
+        message::{
+            message::Payload, wal_record::Record, Checkpoint, Commit, Message, NodeStatus,
+            PrePrepare, Prepare, PreparedCert, Reply, Request, Snapshot, ViewChange, ViewChangeAck,
+        },
+        metrics::{DropReason, Metrics, RejectReason},
+        peers::{ConnectionState, Peers, Transport},
+        pool::RequestHandler,
+        quorum, recovery,
+        reply::Replies,
+        server::Options,
+        state_machine::StateMachine,
+        tls, transfer, view_change,
+        wal::{SyncPolicy, Wal},
     };
-    use std::env;
+    use std::{collections::HashMap, env, fs, io::Write, sync::Arc, time::Duration};
+    use tokio::sync::mpsc;
+
+    /// a pool of node `local` among nodes 1..=4 running on its own task,
+    /// recovered from the log at `wal` if any
+    async fn start_pool(
+        local: usize,
+        options: &Options,
+        wal: Option<&std::path::Path>,
+    ) -> (mpsc::Sender<Message>, mpsc::Receiver<Event>, Arc<Metrics>) {
+        let list: HashMap<usize, String> = (1..=4).map(|id| (id, String::new())).collect();
+        let (tx_req, rv_req) = mpsc::channel(1024);
+        let (_, rv_checkpoint) = mpsc::unbounded_channel();
+        let (tx_event, rv_event) = mpsc::channel(1024);
+        let metrics = Arc::new(Metrics::default());
+        let mut handler = RequestHandler::new(
+            Arc::new(Members::new(local, &list)),
+            rv_req,
+            rv_checkpoint,
+            tx_event,
+            options,
+            metrics.clone(),
+            Arc::new(Replies::default()),
+            Arc::new(transfer::Store::default()),
+        );
+        if let Some(path) = wal {
+            let (wal, records) = Wal::open(path, SyncPolicy::Never).unwrap();
+            handler.recover(wal, records).await;
+        }
+        tokio::spawn(async move { handler.start().await });
+        (tx_req, rv_event, metrics)
+    }
+
+    /// an executor of node 1 among nodes 1..=4 and the sender of its events
+    fn event_handler<S: StateMachine>(
+        state_machine: S,
+    ) -> (EventHandler<Members, S>, mpsc::Sender<Event>, Arc<Replies>) {
+        let list: HashMap<usize, String> = (1..=4).map(|id| (id, String::new())).collect();
+        let (tx_event, rv_event) = mpsc::channel(1024);
+        let (tx_pool, _) = mpsc::unbounded_channel();
+        let replies = Arc::new(Replies::default());
+        let handler = EventHandler::new(
+            Arc::new(Members::new(1, &list)),
+            rv_event,
+            tx_pool,
+            &Options::default(),
+            state_machine,
+            replies.clone(),
+            Arc::new(transfer::Store::default()),
+            Arc::new(Peers::default()),
+        );
+        (handler, tx_event, replies)
+    }
+
+    async fn next_event(events: &mut mpsc::Receiver<Event>) -> Event {
+        tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    fn pre_prepare(view: u64, seq: u64, id: u64) -> Message {
+        Message {
+            view,
+            seq,
+            id,
+            digest: batch_digest(&[]),
+            payload: Some(Payload::PrePrepare(PrePrepare::default())),
+        }
+    }
 
     #[test]
     fn build_proto() {
         env::set_var("OUT_DIR", "src/");
-        tonic_build::compile_protos("protos/message.proto").unwrap();
+        // authenticators are encoded inside signed view changes, so they need a stable order
+        tonic_build::configure()
+            .btree_map([
+                ".message.PrePrepare.authenticator",
+                ".message.Prepare.authenticator",
+                ".message.Commit.authenticator",
+            ])
+            .compile(&["protos/message.proto"], &["protos"])
+            .unwrap();
     }
 
     #[tokio::test]
     async fn start_client() {
         let msg = Message {
             view: 1,
-            seq: 3,
+            seq: 0,
             id: 0,
             digest: "".to_string(),
             payload: Some(Payload::Request(Request {
                 payload: Vec::new(),
+                client: 0,
+                timestamp: 1,
+            })),
+        };
+        send("http://127.0.0.1:8080".parse().unwrap(), msg)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn new_view_pre_prepares() {
+        let view_change = |id: u64, stable_checkpoint: u64, prepared: Vec<PreparedCert>| Message {
+            view: 2,
+            seq: stable_checkpoint,
+            id,
+            digest: "".to_string(),
+            payload: Some(Payload::ViewChange(ViewChange {
+                new_view: 2,
+                stable_checkpoint,
+                prepared,
+                signature: vec![],
+                checkpoints: vec![],
+            })),
+        };
+        let cert = |view: u64, seq: u64, payload: &[u8]| PreparedCert {
+            view,
+            seq,
+            digest: "".to_string(),
+            requests: vec![Request {
+                payload: payload.to_vec(),
+                client: 1,
+                timestamp: seq,
+            }],
+            ..Default::default()
+        };
+        let view_changes = vec![
+            view_change(1, 0, vec![cert(1, 1, b"a"), cert(0, 3, b"old")]),
+            view_change(2, 1, vec![cert(1, 3, b"new")]),
+            view_change(3, 0, vec![]),
+        ];
+
+        let pre_prepares = view_change::pre_prepares(2, 2, &view_changes);
+        let seqs: Vec<u64> = pre_prepares.iter().map(|m| m.seq).collect();
+        assert_eq!(seqs, vec![2, 3]);
+        match (&pre_prepares[0].payload, &pre_prepares[1].payload) {
+            (Some(Payload::PrePrepare(null)), Some(Payload::PrePrepare(p))) => {
+                assert!(null.requests.is_empty());
+                assert_eq!(p.requests[0].payload, b"new".to_vec());
+            }
+            _ => panic!("expect pre-prepare"),
+        }
+    }
+
+    #[tokio::test]
+    async fn pre_prepare_from_primary_only() {
+        // node 2 is the primary of view 1
+        let (pool, mut events, metrics) = start_pool(1, &Options::default(), None).await;
+        pool.send(pre_prepare(1, 1, 3)).await.unwrap();
+        pool.send(pre_prepare(1, 1, 2)).await.unwrap();
+
+        let event = next_event(&mut events).await;
+        assert!(matches!(event.event_type, EventType::Broadcast));
+        assert!(matches!(event.msg.payload, Some(Payload::Prepare(_))));
+        assert_eq!(metrics.rejected(RejectReason::InvalidPrePrepare), 1);
+    }
+
+    #[tokio::test]
+    async fn view_change_needs_proven_certs() {
+        let (pool, mut events, metrics) = start_pool(1, &Options::default(), None).await;
+        let view_change = |seq: u64, prepares: &[u64]| Message {
+            view: 2,
+            seq: 0,
+            id: 4,
+            digest: String::new(),
+            payload: Some(Payload::ViewChange(ViewChange {
+                new_view: 2,
+                stable_checkpoint: 0,
+                prepared: vec![PreparedCert {
+                    view: 1,
+                    seq,
+                    digest: batch_digest(&[]),
+                    requests: vec![],
+                    // node 2 is the primary of view 1
+                    pre_prepare: Some(pre_prepare(1, seq, 2)),
+                    prepares: prepares
+                        .iter()
+                        .map(|id| Message {
+                            view: 1,
+                            seq,
+                            id: *id,
+                            digest: batch_digest(&[]),
+                            payload: Some(Payload::Prepare(Prepare::default())),
+                        })
+                        .collect(),
+                }],
+                signature: vec![],
+                checkpoints: vec![],
+            })),
+        };
+
+        // the prepare of the primary does not count, 2f = 2 backups are needed
+        pool.send(view_change(1, &[2, 4])).await.unwrap();
+        // beyond the high watermark of the stable checkpoint
+        let window = Options::default().window as u64;
+        pool.send(view_change(window, &[3, 4])).await.unwrap();
+        pool.send(view_change(1, &[3, 4])).await.unwrap();
+        // node 3 is the primary of view 2, the others acknowledge the view-change
+        let event = next_event(&mut events).await;
+        assert!(matches!(event.msg.payload, Some(Payload::ViewChangeAck(_))));
+        assert_eq!(metrics.rejected(RejectReason::InvalidViewChange), 2);
+    }
+
+    #[tokio::test]
+    async fn latest_view_change_per_sender() {
+        let (pool, mut events, _) = start_pool(1, &Options::default(), None).await;
+        let view_change = |new_view: u64| Message {
+            view: new_view,
+            seq: 0,
+            id: 4,
+            digest: String::new(),
+            payload: Some(Payload::ViewChange(ViewChange {
+                new_view,
+                ..Default::default()
+            })),
+        };
+
+        pool.send(view_change(3)).await.unwrap();
+        let event = next_event(&mut events).await;
+        assert!(
+            matches!(event.msg.payload, Some(Payload::ViewChangeAck(ref ack)) if ack.new_view == 3)
+        );
+        // node 4 already asked for view 3, its view-change for view 2 is not kept
+        pool.send(view_change(2)).await.unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), events.recv())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn snapshot_checked_before_restore() {
+        struct Fixed;
+        impl StateMachine for Fixed {
+            fn execute(&mut self, _: u64, _: &[u8]) -> Vec<u8> {
+                vec![]
+            }
+            fn snapshot(&self) -> Vec<u8> {
+                vec![]
+            }
+            fn restore(&mut self, snapshot: &[u8]) -> Result<(), ConsensusError> {
+                assert_ne!(
+                    snapshot, b"forged",
+                    "state replaced before its digest is checked"
+                );
+                Ok(())
+            }
+            fn digest(&self) -> String {
+                String::new()
+            }
+            fn snapshot_digest(snapshot: &[u8]) -> Result<String, ConsensusError> {
+                Ok(String::from_utf8_lossy(snapshot).into_owned())
+            }
+        }
+        let (mut handler, _, replies) = event_handler(Fixed);
+        let reply = |result: &[u8]| Reply {
+            view: 1,
+            timestamp: 1,
+            client: 7,
+            replica: 3,
+            result: result.to_vec(),
+        };
+        let snapshot = Snapshot {
+            seq: 5,
+            digest: checkpoint_digest("state", &[reply(b"ok")]),
+            state: b"forged".to_vec(),
+            replies: vec![reply(b"ok")],
+        };
+        assert!(matches!(
+            handler.restore(snapshot.clone()).await,
+            Err(ConsensusError::SnapshotMismatch(5))
+        ));
+        // the reply table is covered by the digest too
+        let tampered = Snapshot {
+            state: b"state".to_vec(),
+            replies: vec![reply(b"forged")],
+            ..snapshot.clone()
+        };
+        assert!(handler.restore(tampered).await.is_err());
+        assert_eq!(handler.commited_seq(), 0);
+        assert!(replies.last(7).await.is_none());
+
+        let snapshot = Snapshot {
+            state: b"state".to_vec(),
+            ..snapshot
+        };
+        handler.restore(snapshot).await.unwrap();
+        assert_eq!(handler.commited_seq(), 5);
+        // cached replies are answered as this replica's own
+        assert_eq!(
+            replies.last(7).await,
+            Some(Reply {
+                replica: 1,
+                ..reply(b"ok")
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn conflicting_votes_never_form_a_quorum() {
+        let (pool, mut events, metrics) = start_pool(1, &Options::default(), None).await;
+        let vote = |id: u64, digest: &str, payload: Payload| Message {
+            view: 1,
+            seq: 1,
+            id,
+            digest: digest.to_string(),
+            payload: Some(payload),
+        };
+        let prepare = || Payload::Prepare(Prepare::default());
+        let commit = || Payload::Commit(Commit::default());
+        async fn quiet(events: &mut mpsc::Receiver<Event>) -> bool {
+            tokio::time::timeout(Duration::from_millis(200), events.recv())
+                .await
+                .is_err()
+        }
+
+        // a pre-prepare whose digest is not the one of its batch
+        let mut forged = pre_prepare(1, 2, 2);
+        forged.digest = "forged".to_string();
+        pool.send(forged).await.unwrap();
+        assert!(quiet(&mut events).await);
+        assert_eq!(metrics.rejected(RejectReason::DigestMismatch), 1);
+
+        let digest = batch_digest(&[]);
+        pool.send(pre_prepare(1, 1, 2)).await.unwrap();
+        let event = next_event(&mut events).await;
+        assert!(matches!(event.msg.payload, Some(Payload::Prepare(_))));
+
+        // own prepare and one for another digest are not 2f matching prepares
+        pool.send(vote(3, "forged", prepare())).await.unwrap();
+        assert!(quiet(&mut events).await);
+        pool.send(vote(4, &digest, prepare())).await.unwrap();
+        let event = next_event(&mut events).await;
+        assert!(matches!(event.msg.payload, Some(Payload::Commit(_))));
+
+        // own commit, one for another digest and one matching are not 2f+1 matching commits
+        pool.send(vote(2, "forged", commit())).await.unwrap();
+        pool.send(vote(3, &digest, commit())).await.unwrap();
+        assert!(quiet(&mut events).await);
+        pool.send(vote(4, &digest, commit())).await.unwrap();
+        let event = next_event(&mut events).await;
+        assert!(matches!(event.event_type, EventType::Commit));
+        assert_eq!(event.msg.seq, 1);
+    }
+
+    #[tokio::test]
+    async fn ring_buffer_slides_with_stable_checkpoints() {
+        let options = Options {
+            window: 4,
+            ..Default::default()
+        };
+        let (pool, mut events, metrics) = start_pool(1, &options, None).await;
+        let vote = |seq: u64, id: u64, payload: Payload| Message {
+            view: 1,
+            seq,
+            id,
+            digest: batch_digest(&[]),
+            payload: Some(payload),
+        };
+        let checkpoint = |seq: u64, id: u64| Message {
+            view: 1,
+            seq,
+            id,
+            digest: "state".to_string(),
+            payload: Some(Payload::Checkpoint(Checkpoint {
+                seq,
+                digest: "state".to_string(),
+                signature: vec![],
+            })),
+        };
+        let order = |seq: u64| {
+            let mut messages = vec![pre_prepare(1, seq, 2)];
+            for id in [3, 4] {
+                messages.push(vote(seq, id, Payload::Prepare(Prepare::default())));
+            }
+            for id in [2, 3, 4] {
+                messages.push(vote(seq, id, Payload::Commit(Commit::default())));
+            }
+            messages
+        };
+
+        // the window (0, 4) holds sequences 1 to 3 only
+        let mut messages: Vec<Message> = (1..=4).flat_map(order).collect();
+        messages.extend((2..=4).map(|id| checkpoint(2, id)));
+        // (2, 6) after the first stable checkpoint, (4, 8) after the second
+        messages.extend((4..=5).flat_map(order));
+        messages.extend((2..=4).map(|id| checkpoint(4, id)));
+        messages.extend((6..=7).flat_map(order));
+        for m in messages {
+            pool.send(m).await.unwrap();
+        }
+
+        let mut commited = Vec::new();
+        while commited.len() < 7 {
+            let event = next_event(&mut events).await;
+            if matches!(event.event_type, EventType::Commit) {
+                commited.push(event.msg.seq);
+            }
+        }
+        assert_eq!(commited, vec![1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(metrics.stable_checkpoint(), 4);
+        assert_eq!(metrics.rejected(RejectReason::OutOfWindow), 6);
+    }
+
+    #[tokio::test]
+    async fn checkpoints_ahead_of_window_start_transfer() {
+        let options = Options {
+            window: 4,
+            ..Default::default()
+        };
+        let (pool, mut events, metrics) = start_pool(1, &options, None).await;
+        let checkpoint = |seq: u64, id: u64, digest: &str| Message {
+            view: 1,
+            seq,
+            id,
+            digest: digest.to_string(),
+            payload: Some(Payload::Checkpoint(Checkpoint {
+                seq,
+                digest: digest.to_string(),
+                signature: vec![],
+            })),
+        };
+
+        // the others moved on past the high watermark 4, a single one proves nothing
+        pool.send(checkpoint(8, 2, "state")).await.unwrap();
+        pool.send(checkpoint(8, 3, "forged")).await.unwrap();
+        pool.send(checkpoint(6, 2, "state")).await.unwrap();
+        pool.send(checkpoint(8, 4, "state")).await.unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), events.recv())
+                .await
+                .is_err()
+        );
+        // node 3 moves on too, 2f+1 matching checkpoints at 12
+        for id in 2..=4 {
+            pool.send(checkpoint(12, id, "later")).await.unwrap();
+        }
+        let event = next_event(&mut events).await;
+        assert!(matches!(event.event_type, EventType::Stable));
+        assert_eq!(event.msg.seq, 12);
+        assert_eq!(metrics.stable_checkpoint(), 12);
+        assert_eq!(metrics.dropped(DropReason::CheckpointAhead), 7);
+        assert_eq!(metrics.dropped(DropReason::StaleCheckpoint), 0);
+    }
+
+    #[tokio::test]
+    async fn full_window_does_not_depose_primary() {
+        let options = Options {
+            window: 4,
+            request_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let (pool, mut events, _) = start_pool(1, &options, None).await;
+        let request = Message {
+            payload: Some(Payload::Request(Request {
+                payload: vec![],
+                client: 7,
+                timestamp: 1,
+            })),
+            ..Default::default()
+        };
+        let checkpoint = |id: u64| Message {
+            view: 1,
+            seq: 2,
+            id,
+            digest: "state".to_string(),
+            payload: Some(Payload::Checkpoint(Checkpoint {
+                seq: 2,
+                digest: "state".to_string(),
+                signature: vec![],
+            })),
+        };
+        async fn view_changed(events: &mut mpsc::Receiver<Event>, wait: u64) -> bool {
+            let view_change = async {
+                while let Some(event) = events.recv().await {
+                    if matches!(event.msg.payload, Some(Payload::ViewChange(_))) {
+                        return;
+                    }
+                }
+            };
+            tokio::time::timeout(Duration::from_millis(wait), view_change)
+                .await
+                .is_ok()
+        }
+
+        // the primary fills the window (0, 4), it answers busy to the request meanwhile
+        pool.send(request).await.unwrap();
+        for seq in 1..=3 {
+            pool.send(pre_prepare(1, seq, 2)).await.unwrap();
+        }
+        // and the window slides
+        for id in 2..=4 {
+            pool.send(checkpoint(id)).await.unwrap();
+        }
+        assert!(!view_changed(&mut events, 300).await);
+
+        // a full window that does not slide is held against the primary
+        for seq in 4..=5 {
+            pool.send(pre_prepare(1, seq, 2)).await.unwrap();
+        }
+        assert!(view_changed(&mut events, 1000).await);
+    }
+
+    #[tokio::test]
+    async fn execute_in_sequence_order() {
+        struct Log(Arc<std::sync::Mutex<Vec<u64>>>);
+        impl StateMachine for Log {
+            fn execute(&mut self, seq: u64, _: &[u8]) -> Vec<u8> {
+                self.0.lock().unwrap().push(seq);
+                vec![]
+            }
+            fn snapshot(&self) -> Vec<u8> {
+                vec![]
+            }
+            fn restore(&mut self, _: &[u8]) -> Result<(), ConsensusError> {
+                Ok(())
+            }
+            fn digest(&self) -> String {
+                String::new()
+            }
+            fn snapshot_digest(_: &[u8]) -> Result<String, ConsensusError> {
+                Ok(String::new())
+            }
+        }
+        let executed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (mut handler, events, _) = event_handler(Log(executed.clone()));
+        let commit = |seq: u64| {
+            let requests = vec![Request {
+                payload: vec![],
+                client: seq,
+                timestamp: 1,
+            }];
+            Event::new_commit(Message {
+                view: 1,
+                seq,
+                id: 2,
+                digest: batch_digest(&requests),
+                payload: Some(Payload::PrePrepare(PrePrepare {
+                    requests,
+                    ..Default::default()
+                })),
+            })
+        };
+        for seq in [3, 1, 3, 2, 1] {
+            events.send(commit(seq)).await.unwrap();
+        }
+        drop(events);
+        handler.start().await;
+
+        assert_eq!(*executed.lock().unwrap(), vec![1, 2, 3]);
+        assert_eq!(handler.commited_seq(), 3);
+    }
+
+    #[tokio::test]
+    async fn new_primary_numbers_after_reissued_pre_prepares() {
+        // node 3 is the primary of view 2
+        let (pool, mut events, _) = start_pool(3, &Options::default(), None).await;
+        let view_change = |id: u64| Message {
+            view: 2,
+            seq: 0,
+            id,
+            digest: String::new(),
+            payload: Some(Payload::ViewChange(ViewChange {
+                new_view: 2,
+                ..Default::default()
+            })),
+        };
+        let ack = |node: u64| Message {
+            view: 2,
+            seq: 0,
+            id: 4,
+            digest: String::new(),
+            payload: Some(Payload::ViewChangeAck(ViewChangeAck {
+                new_view: 2,
+                node,
+                signature: vec![],
+            })),
+        };
+
+        // pre-prepared as a backup in view 1 but never prepared
+        for seq in 1..=3 {
+            pool.send(pre_prepare(1, seq, 2)).await.unwrap();
+        }
+        for id in [1, 2] {
+            pool.send(view_change(id)).await.unwrap();
+        }
+        for node in [1, 2] {
+            pool.send(ack(node)).await.unwrap();
+        }
+        pool.send(Message {
+            payload: Some(Payload::Request(Request {
+                payload: vec![],
+                client: 7,
+                timestamp: 1,
+            })),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // nothing prepared, so nothing is re-issued and the new view starts at sequence 1
+        loop {
+            let event = next_event(&mut events).await;
+            if let Some(Payload::PrePrepare(_)) = event.msg.payload {
+                assert_eq!((event.msg.view, event.msg.seq), (2, 1));
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn request_timeout_starts_view_change_with_backoff() {
+        let base = Duration::from_millis(100);
+        let options = Options {
+            request_timeout: Duration::from_millis(50),
+            view_change_timeout: base,
+            ..Default::default()
+        };
+        let (pool, mut events, _) = start_pool(1, &options, None).await;
+        pool.send(Message {
+            payload: Some(Payload::Request(Request {
+                payload: vec![],
+                client: 7,
+                timestamp: 1,
+            })),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // the primary never orders the request, the backup suspects it
+        let mut started = Vec::new();
+        for new_view in 2..=5 {
+            let event = next_event(&mut events).await;
+            match event.msg.payload {
+                Some(Payload::ViewChange(ref vc)) => assert_eq!(vc.new_view, new_view),
+                _ => panic!("expect view-change"),
+            }
+            started.push(tokio::time::Instant::now());
+        }
+        // every view change that times out waits twice as long for the next one
+        for (attempt, pair) in started.windows(2).enumerate() {
+            let waited = pair[1] - pair[0];
+            let timeout = base * 2u32.pow(attempt as u32);
+            assert!(
+                waited >= timeout && waited < timeout * 2,
+                "attempt {} waited {:?}, timeout {:?}",
+                attempt,
+                waited,
+                timeout
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn resume_logged_view_change() {
+        let dir = env::temp_dir().join(format!("pbft-resume-{}", std::process::id()));
+        let path = dir.join("pool.wal");
+        let _ = fs::remove_dir_all(&dir);
+        let (mut wal, _) = Wal::open(&path, SyncPolicy::Never).unwrap();
+        wal.append_message(&Message {
+            view: 2,
+            seq: 0,
+            id: 1,
+            digest: String::new(),
+            payload: Some(Payload::ViewChange(ViewChange {
+                new_view: 2,
+                ..Default::default()
+            })),
+        })
+        .unwrap();
+        drop(wal);
+
+        let options = Options {
+            view_change_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let (_pool, mut events, _) = start_pool(1, &options, Some(&path)).await;
+        // announced again after the restart, then timed out into the next view
+        for new_view in [2, 3] {
+            let event = next_event(&mut events).await;
+            match event.msg.payload {
+                Some(Payload::ViewChange(ref vc)) => assert_eq!(vc.new_view, new_view),
+                _ => panic!("expect view-change"),
+            }
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let (signing_key, verifying_key) = crypto::generate_keypair();
+        let (_, other_key) = crypto::generate_keypair();
+        let mut msg = Message {
+            view: 1,
+            seq: 1,
+            id: 2,
+            digest: "digest".to_string(),
+            payload: Some(Payload::Prepare(Prepare::default())),
+        };
+        assert!(!crypto::verify(&msg, &verifying_key));
+
+        crypto::sign(&mut msg, &signing_key);
+        assert!(crypto::verify(&msg, &verifying_key));
+        assert!(!crypto::verify(&msg, &other_key));
+
+        msg.digest = "other".to_string();
+        assert!(!crypto::verify(&msg, &verifying_key));
+    }
+
+    #[test]
+    fn mac_authenticators() {
+        let keys: HashMap<usize, crypto::MacKey> = HashMap::from([(2, [2u8; 32]), (3, [3u8; 32])]);
+        let mut msg = Message {
+            view: 1,
+            seq: 1,
+            id: 1,
+            digest: "digest".to_string(),
+            payload: Some(Payload::Commit(Commit::default())),
+        };
+        assert!(crypto::has_authenticator(&msg));
+        assert!(!crypto::verify_mac(&msg, 2, &keys[&2]));
+
+        crypto::authenticate(&mut msg, &keys);
+        assert!(crypto::verify_mac(&msg, 2, &keys[&2]));
+        assert!(crypto::verify_mac(&msg, 3, &keys[&3]));
+        // each replica checks only the mac made with its own key
+        assert!(!crypto::verify_mac(&msg, 3, &keys[&2]));
+        assert!(!crypto::verify_mac(&msg, 4, &keys[&2]));
+
+        msg.digest = "other".to_string();
+        assert!(!crypto::verify_mac(&msg, 2, &keys[&2]));
+
+        let checkpoint = Message {
+            payload: Some(Payload::Checkpoint(Checkpoint::default())),
+            ..Default::default()
+        };
+        assert!(!crypto::has_authenticator(&checkpoint));
+    }
+
+    #[test]
+    fn signed_view_change_with_macs_survives_decoding() {
+        use prost::Message as _;
+        let (signing_key, verifying_key) = crypto::generate_keypair();
+        let keys: HashMap<usize, crypto::MacKey> = (1..=8).map(|id| (id, [id as u8; 32])).collect();
+        let prepares = (2..=4)
+            .map(|id| {
+                let mut prepare = Message {
+                    view: 1,
+                    seq: 1,
+                    id,
+                    digest: batch_digest(&[]),
+                    payload: Some(Payload::Prepare(Prepare::default())),
+                };
+                crypto::authenticate(&mut prepare, &keys);
+                prepare
+            })
+            .collect();
+        let mut msg = Message {
+            view: 2,
+            seq: 0,
+            id: 4,
+            digest: String::new(),
+            payload: Some(Payload::ViewChange(ViewChange {
+                new_view: 2,
+                stable_checkpoint: 0,
+                prepared: vec![PreparedCert {
+                    view: 1,
+                    seq: 1,
+                    digest: batch_digest(&[]),
+                    requests: vec![],
+                    pre_prepare: None,
+                    prepares,
+                }],
+                signature: vec![],
+                checkpoints: vec![],
             })),
         };
-        send("http://127.0.0.1:8080", msg).await.unwrap();
+        crypto::sign(&mut msg, &signing_key);
+        // the receiver verifies a decoded copy, the nested authenticators must re-encode alike
+        for _ in 0..20 {
+            let decoded = Message::decode(msg.encode_to_vec().as_slice()).unwrap();
+            assert!(crypto::verify(&decoded, &verifying_key));
+        }
+    }
+
+    #[test]
+    fn primary_rotates_with_view() {
+        let list: HashMap<usize, String> = [7, 3, 5, 1]
+            .into_iter()
+            .map(|id| (id, format!("http://127.0.0.1:{}", 8080 + id)))
+            .collect();
+        let nodes: Vec<Members> = list.keys().map(|id| Members::new(*id, &list)).collect();
+        let leaders = |nodes: &[Members]| -> Vec<usize> {
+            nodes
+                .iter()
+                .filter(|m| m.is_leader())
+                .map(|m| m.local_id())
+                .collect()
+        };
+        // sorted ids are 1, 3, 5, 7 and replicas start in view 1
+        assert_eq!(leaders(&nodes), vec![3]);
+        for (view, primary) in [(2, 5), (3, 7), (4, 1), (9, 3)] {
+            for m in nodes.iter() {
+                m.enter_view(view);
+            }
+            assert_eq!(leaders(&nodes), vec![primary]);
+        }
+    }
+
+    #[test]
+    fn versioned_membership() {
+        let list: HashMap<usize, String> = (1..=4)
+            .map(|id| (id, format!("http://127.0.0.1:{}", 8079 + id)))
+            .collect();
+        let members = Members::new(1, &list);
+        assert_eq!(members.epoch(), 0);
+        assert!(members
+            .add_node(2, String::from("http://127.0.0.1:9000"))
+            .is_err());
+        assert!(members.delete_node(9).is_err());
+        // 3 members cannot tolerate a faulty node
+        assert!(members.delete_node(4).is_err());
+        assert_eq!(members.members(), list);
+
+        // readers never see a partial or empty list while it changes
+        let reader = {
+            let members = members.clone();
+            std::thread::spawn(move || {
+                for _ in 0..10000 {
+                    let snapshot = members.snapshot();
+                    assert!(snapshot.list.len() == 4 || snapshot.list.len() == 5);
+                }
+            })
+        };
+        for _ in 0..100 {
+            members
+                .add_node(5, String::from("http://127.0.0.1:8084"))
+                .unwrap();
+            members.delete_node(5).unwrap();
+        }
+        reader.join().unwrap();
+        assert_eq!(members.epoch(), 200);
+        assert_eq!(members.members(), list);
+    }
+
+    #[test]
+    fn drop_metrics() {
+        let metrics = Metrics::default();
+        assert_eq!(metrics.drop_message(DropReason::WindowFull), 1);
+        assert_eq!(metrics.drop_message(DropReason::WindowFull), 2);
+        assert_eq!(metrics.drop_message(DropReason::ViewChanging), 1);
+        assert_eq!(metrics.dropped(DropReason::QueueFull), 0);
+        let all = metrics.dropped_all();
+        assert_eq!(all.len(), DropReason::ALL.len());
+        assert!(all.contains(&(DropReason::WindowFull, 2)));
+        assert!(all.contains(&(DropReason::ViewChanging, 1)));
+    }
+
+    #[test]
+    fn quorum_sizes() {
+        // (n, f, prepare, commit, reply)
+        for (n, f, prepare, commit, reply) in [
+            (4, 1, 2, 3, 2),
+            (5, 1, 3, 4, 2),
+            (6, 1, 3, 4, 2),
+            (7, 2, 4, 5, 3),
+            (8, 2, 5, 6, 3),
+            (10, 3, 6, 7, 4),
+        ] {
+            assert_eq!(quorum::max_faulty(n), f);
+            assert_eq!(quorum::prepare_quorum(n), prepare);
+            assert_eq!(quorum::commit_quorum(n), commit);
+            assert_eq!(quorum::reply_quorum(n), reply);
+        }
+        assert!(quorum::validate(3).is_err());
+        assert!(quorum::validate(4).is_ok());
+    }
+
+    #[test]
+    fn accept_matching_replies() {
+        let reply = |member: usize, result: &[u8]| {
+            (
+                member,
+                Reply {
+                    view: 1,
+                    timestamp: 1,
+                    client: 7,
+                    replica: member as u64,
+                    result: result.to_vec(),
+                },
+            )
+        };
+        // f = 1 for 4 replicas, f+1 matching replies from different replicas are needed
+        let quorum = quorum::reply_quorum(4);
+        // a member claiming the id of another replica still votes once
+        let (_, mut forged) = reply(1, b"ok");
+        forged.replica = 2;
+        assert_eq!(accept(&[reply(1, b"ok"), (1, forged)], quorum), None);
+        assert_eq!(accept(&[reply(1, b"ok")], quorum), None);
+        assert_eq!(accept(&[reply(1, b"ok"), reply(1, b"ok")], quorum), None);
+        assert_eq!(accept(&[reply(1, b"ok"), reply(2, b"bad")], quorum), None);
+        assert_eq!(
+            accept(
+                &[reply(1, b"ok"), reply(2, b"bad"), reply(3, b"ok")],
+                quorum
+            ),
+            Some(b"ok".to_vec())
+        );
+    }
+
+    #[test]
+    fn batch_digests() {
+        let request = |timestamp: u64| Request {
+            payload: b"op".to_vec(),
+            client: 1,
+            timestamp,
+        };
+        // the same payload from a retransmission is a different request
+        assert_ne!(request_digest(&request(1)), request_digest(&request(2)));
+        // batches are ordered, the same requests in another order are another batch
+        assert_ne!(
+            batch_digest(&[request(1), request(2)]),
+            batch_digest(&[request(2), request(1)])
+        );
+        assert_ne!(batch_digest(&[]), batch_digest(&[request(1)]));
+    }
+
+    /// send `count` requests of `bytes` bytes to node 2, the primary of view 1, and
+    /// return the number of requests in each batch it ordered within `wait`
+    async fn batches(options: Options, count: u64, bytes: usize, wait: Duration) -> Vec<usize> {
+        let (pool, mut events, _) = start_pool(2, &options, None).await;
+        for timestamp in 1..=count {
+            pool.send(Message {
+                payload: Some(Payload::Request(Request {
+                    payload: vec![0; bytes],
+                    client: 7,
+                    timestamp,
+                })),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+        let mut sizes = Vec::new();
+        while let Ok(Some(event)) = tokio::time::timeout(wait, events.recv()).await {
+            if let Some(Payload::PrePrepare(ref pre_prepare)) = event.msg.payload {
+                sizes.push(pre_prepare.requests.len());
+            }
+        }
+        sizes
+    }
+
+    #[tokio::test]
+    async fn batch_closes_on_size() {
+        let options = Options {
+            batch_size: 2,
+            batch_delay: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let sizes = batches(options, 5, 4, Duration::from_millis(200)).await;
+        assert_eq!(sizes, vec![2, 2]);
+    }
+
+    #[tokio::test]
+    async fn batch_closes_before_crossing_bytes() {
+        let options = Options {
+            batch_bytes: 10,
+            batch_delay: Duration::from_secs(10),
+            ..Default::default()
+        };
+        // a third request of 4 bytes would take the batch to 12
+        let sizes = batches(options, 5, 4, Duration::from_millis(200)).await;
+        assert_eq!(sizes, vec![2, 2]);
+        // a request larger than the limit is ordered alone
+        let options = Options {
+            batch_bytes: 10,
+            batch_delay: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let sizes = batches(options, 2, 16, Duration::from_millis(200)).await;
+        assert_eq!(sizes, vec![1, 1]);
+    }
+
+    #[tokio::test]
+    async fn batch_closes_on_delay() {
+        let options = Options {
+            batch_delay: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let sizes = batches(options, 3, 4, Duration::from_millis(500)).await;
+        assert_eq!(sizes, vec![3]);
+    }
+
+    #[tokio::test]
+    async fn last_reply_per_client() {
+        let reply = |timestamp: u64, result: &[u8]| Reply {
+            view: 1,
+            timestamp,
+            client: 7,
+            replica: 1,
+            result: result.to_vec(),
+        };
+        let replies = Replies::default();
+        assert!(replies.last(7).await.is_none());
+        replies.record(reply(2, b"second")).await;
+        // an older reply never replaces a newer one
+        replies.record(reply(1, b"first")).await;
+        assert_eq!(replies.last(7).await, Some(reply(2, b"second")));
+        assert!(replies.last(8).await.is_none());
+    }
+
+    #[test]
+    fn wal_replay_and_torn_tail() {
+        let dir = env::temp_dir().join(format!("pbft-wal-{}", std::process::id()));
+        let path = dir.join("pool.wal");
+        let _ = fs::remove_dir_all(&dir);
+        let prepare = |seq: u64| Message {
+            view: 1,
+            seq,
+            id: 2,
+            digest: "digest".to_string(),
+            payload: Some(Payload::Prepare(Prepare::default())),
+        };
+
+        let (mut wal, records) = Wal::open(&path, SyncPolicy::Always).unwrap();
+        assert!(records.is_empty());
+        wal.append_view(2).unwrap();
+        wal.append_message(&prepare(1)).unwrap();
+        drop(wal);
+        // a crash in the middle of an append
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[9, 0, 0, 0, 1, 2]).unwrap();
+        drop(file);
+
+        let (mut wal, records) = Wal::open(&path, SyncPolicy::Never).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].record, Some(Record::View(2)));
+        assert_eq!(records[1].record, Some(Record::Message(prepare(1))));
+        wal.append_message(&prepare(2)).unwrap();
+        wal.compact(&records[1..]).unwrap();
+        wal.append_message(&prepare(3)).unwrap();
+        drop(wal);
+
+        let (_, records) = Wal::open(&path, SyncPolicy::Never).unwrap();
+        let seqs: Vec<u64> = records
+            .iter()
+            .filter_map(|r| match r.record {
+                Some(Record::Message(ref m)) => Some(m.seq),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(seqs, vec![1, 3]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rejoin_view_from_peers() {
+        let status = |node: u64, view: u64, stable_checkpoint: u64| NodeStatus {
+            node,
+            view,
+            stable_checkpoint,
+        };
+        // a single faulty peer cannot drag a recovering node into its view
+        let statuses = vec![status(2, 9, 20), status(3, 3, 10), status(4, 2, 10)];
+        assert_eq!(recovery::current_view(&statuses, 4), Some(3));
+        assert_eq!(recovery::stable_checkpoint(&statuses, 4), Some(10));
+        assert_eq!(recovery::current_view(&statuses[..1], 4), None);
+    }
+
+    #[test]
+    fn verify_transferred_checkpoint() {
+        let list: HashMap<usize, String> = (1..=4).map(|id| (id, String::new())).collect();
+        let keys: Vec<_> = (0..=4).map(|_| crypto::generate_keypair()).collect();
+        let members =
+            Members::new(1, &list).with_public_keys((1..=4).map(|id| (id, keys[id].1)).collect());
+        let snapshot = Snapshot {
+            seq: 5,
+            digest: "state".to_string(),
+            state: vec![],
+            replies: vec![],
+        };
+        let checkpoint = |id: usize, digest: &str| {
+            let mut m = Message {
+                view: 1,
+                seq: 5,
+                id: id as u64,
+                digest: digest.to_string(),
+                payload: Some(Payload::Checkpoint(Checkpoint {
+                    seq: 5,
+                    digest: digest.to_string(),
+                    signature: vec![],
+                })),
+            };
+            crypto::sign(&mut m, &keys[id].0);
+            m
+        };
+
+        let proof = vec![checkpoint(2, "state"), checkpoint(3, "state")];
+        assert!(!transfer::checkpoint_proven(
+            &snapshot, &proof, &members, true
+        ));
+        let mut proof = proof;
+        proof.push(checkpoint(4, "other"));
+        assert!(!transfer::checkpoint_proven(
+            &snapshot, &proof, &members, true
+        ));
+        // a checkpoint signed with another key does not count
+        let mut forged = checkpoint(4, "state");
+        crypto::sign(&mut forged, &keys[0].0);
+        proof.push(forged);
+        assert!(!transfer::checkpoint_proven(
+            &snapshot, &proof, &members, true
+        ));
+        proof.push(checkpoint(4, "state"));
+        assert!(transfer::checkpoint_proven(
+            &snapshot, &proof, &members, true
+        ));
+    }
+
+    #[tokio::test]
+    async fn peer_backoff_and_removal() {
+        let peers = Peers::default();
+        // nothing listens on port 1
+        let addr = "http://127.0.0.1:1";
+        let m = Message::default();
+        assert!(peers.send(2, addr, m.clone()).await.is_err());
+        assert!(matches!(
+            peers.states().await.get(&2),
+            Some(ConnectionState::Failed { failures: 1, .. })
+        ));
+        // the peer is not dialed again before its backoff
+        assert!(matches!(
+            peers.send(2, addr, m).await,
+            Err(crate::error::ConsensusError::PeerUnavailable(2))
+        ));
+
+        peers.retain(&HashMap::from([(1, addr.to_string())])).await;
+        assert!(peers.states().await.is_empty());
+    }
+
+    /// a replica serving only protocol messages, it records which calls carried them
+    #[derive(Clone)]
+    struct Stub {
+        streams: bool,
+        calls: Arc<std::sync::Mutex<Vec<(&'static str, u64)>>>,
+    }
+
+    #[tonic::async_trait]
+    impl crate::message::pbft_server::Pbft for Stub {
+        type MessageStreamStream = std::pin::Pin<
+            Box<
+                dyn tonic::codegen::tokio_stream::Stream<
+                        Item = Result<crate::message::MessageResponse, tonic::Status>,
+                    > + Send,
+            >,
+        >;
+        type FetchCheckpointStream = std::pin::Pin<
+            Box<
+                dyn tonic::codegen::tokio_stream::Stream<
+                        Item = Result<crate::message::SnapshotChunk, tonic::Status>,
+                    > + Send,
+            >,
+        >;
+
+        async fn send_message(
+            &self,
+            request: tonic::Request<Message>,
+        ) -> Result<tonic::Response<crate::message::MessageResponse>, tonic::Status> {
+            let seq = request.into_inner().seq;
+            self.calls.lock().unwrap().push(("unary", seq));
+            Ok(tonic::Response::new(Default::default()))
+        }
+
+        async fn message_stream(
+            &self,
+            request: tonic::Request<tonic::Streaming<Message>>,
+        ) -> Result<tonic::Response<Self::MessageStreamStream>, tonic::Status> {
+            if !self.streams {
+                self.calls.lock().unwrap().push(("unimplemented", 0));
+                return Err(tonic::Status::unimplemented("no streams"));
+            }
+            let mut inbound = request.into_inner();
+            let calls = self.calls.clone();
+            let (tx, rx) = mpsc::channel(1);
+            tokio::spawn(async move {
+                // the response stream stays open as long as the inbound one
+                let _tx = tx;
+                while let Ok(Some(m)) = inbound.message().await {
+                    calls.lock().unwrap().push(("stream", m.seq));
+                }
+            });
+            Ok(tonic::Response::new(Box::pin(
+                tonic::codegen::tokio_stream::wrappers::ReceiverStream::new(rx),
+            )))
+        }
+
+        async fn get_status(
+            &self,
+            _: tonic::Request<crate::message::NodeStatusRequest>,
+        ) -> Result<tonic::Response<NodeStatus>, tonic::Status> {
+            Err(tonic::Status::unimplemented(""))
+        }
+
+        async fn get_metrics(
+            &self,
+            _: tonic::Request<crate::message::MetricsRequest>,
+        ) -> Result<tonic::Response<crate::message::MetricsReport>, tonic::Status> {
+            Err(tonic::Status::unimplemented(""))
+        }
+
+        async fn fetch_checkpoint(
+            &self,
+            _: tonic::Request<crate::message::FetchCheckpointRequest>,
+        ) -> Result<tonic::Response<Self::FetchCheckpointStream>, tonic::Status> {
+            Err(tonic::Status::unimplemented(""))
+        }
+
+        async fn fetch_committed_range(
+            &self,
+            _: tonic::Request<crate::message::CommittedRangeRequest>,
+        ) -> Result<tonic::Response<crate::message::CommittedRange>, tonic::Status> {
+            Err(tonic::Status::unimplemented(""))
+        }
+    }
+
+    /// broadcast five messages from node 1 to a stub node 2, and return the calls it got
+    async fn broadcast_to_stub(streams: bool) -> Vec<(&'static str, u64)> {
+        let stub = Stub {
+            streams,
+            calls: Arc::default(),
+        };
+        let calls = stub.calls.clone();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let incoming =
+            tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(crate::message::pbft_server::PbftServer::new(stub))
+                .serve_with_incoming(incoming),
+        );
+
+        let peers = Arc::new(Peers::new(16, Transport::Stream));
+        let list = HashMap::from([(1, String::new()), (2, addr)]);
+        for seq in 1..=5 {
+            let m = Message {
+                seq,
+                ..Default::default()
+            };
+            peers.broadcast(1, list.clone(), m).await;
+        }
+        let delivered = async {
+            while calls
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, seq)| *seq > 0)
+                .count()
+                < 5
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(2), delivered)
+            .await
+            .unwrap();
+        let calls = calls.lock().unwrap().clone();
+        calls
+    }
+
+    #[tokio::test]
+    async fn broadcast_over_one_stream() {
+        let calls = broadcast_to_stub(true).await;
+        let expected: Vec<(&str, u64)> = (1..=5).map(|seq| ("stream", seq)).collect();
+        assert_eq!(calls, expected);
+    }
+
+    #[tokio::test]
+    async fn broadcast_falls_back_to_unary_once() {
+        let calls = broadcast_to_stub(false).await;
+        // the peer is asked for a stream only once
+        let mut expected = vec![("unimplemented", 0)];
+        expected.extend((1..=5).map(|seq| ("unary", seq)));
+        assert_eq!(calls, expected);
+    }
+
+    #[tokio::test]
+    async fn broadcast_drops_oldest_for_slow_peer() {
+        let peers = std::sync::Arc::new(Peers::new(2, Transport::Stream));
+        let list = HashMap::from([
+            (1, String::from("http://127.0.0.1:1")),
+            (2, String::from("http://127.0.0.1:1")),
+        ]);
+        // a dead peer does not hold up the broadcaster
+        let broadcast = async {
+            for seq in 0..5 {
+                let m = Message {
+                    seq,
+                    ..Default::default()
+                };
+                peers.broadcast(1, list.clone(), m).await;
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(1), broadcast)
+            .await
+            .unwrap();
+        // at most one message is in flight and two are queued
+        assert!(peers.dropped().await[&2] >= 2);
+        assert!(!peers.dropped().await.contains_key(&1));
+
+        peers
+            .retain(&HashMap::from([(1, String::from("http://127.0.0.1:1"))]))
+            .await;
+        assert!(peers.dropped().await.is_empty());
+    }
+
+    #[test]
+    fn peer_certificate_identity() {
+        let params = rcgen::CertificateParams::new(vec![tls::node_name(2)]);
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        let certs = vec![tonic::transport::Certificate::from_pem(
+            cert.serialize_der().unwrap(),
+        )];
+        assert!(tls::is_node(&certs, 2));
+        assert!(!tls::is_node(&certs, 3));
+        assert!(!tls::is_node(&[], 2));
+
+        let dir = env::temp_dir().join(format!("pbft-certs-{}", std::process::id()));
+        tls::write_certificates(&dir, &[1, 2]).unwrap();
+        for name in ["ca.pem", "node1.pem", "node1.key", "node2.pem", "node2.key"] {
+            assert!(dir.join(name).exists());
+        }
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -1,46 +1,105 @@
+use crate::crypto::MacKey;
+use crate::error::ConsensusError;
+use crate::quorum;
+use crate::view_change;
+use arc_swap::ArcSwap;
+use ed25519_dalek::VerifyingKey;
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
-pub trait Membership {
+pub trait Membership: Send + Sync + 'static {
+    /// this node is the primary of the current view
     fn is_leader(&self) -> bool;
-    fn become_leader(&self);
+    fn enter_view(&self, view: usize);
     fn local_id(&self) -> usize;
     fn members(&self) -> HashMap<usize, String>;
-    fn add_node(&self, id: usize, addr: String);
-    fn delete_node(&self, id: usize);
+    /// version of the member list, bumped by every change
+    fn epoch(&self) -> u64;
+    /// add a member, returns the new epoch
+    fn add_node(&self, id: usize, addr: String) -> Result<u64, ConsensusError>;
+    /// remove a member, returns the new epoch
+    fn delete_node(&self, id: usize) -> Result<u64, ConsensusError>;
+    fn public_key(&self, id: usize) -> Option<VerifyingKey>;
+    fn mac_key(&self, id: usize) -> Option<MacKey>;
+}
+
+/// an immutable version of the member list, replaced as a whole on every change
+#[derive(Debug)]
+pub struct MemberSet {
+    pub epoch: u64,
+    pub list: HashMap<usize, String>,
 }
 
 #[derive(Clone)]
 pub struct Members {
     id: usize,
-    is_leader: Arc<AtomicBool>,
-    list: Arc<Mutex<HashMap<usize, String>>>,
+    view: Arc<AtomicUsize>,
+    list: Arc<ArcSwap<MemberSet>>,
+    public_keys: Arc<HashMap<usize, VerifyingKey>>,
+    // key shared with every other member
+    mac_keys: Arc<HashMap<usize, MacKey>>,
 }
 
 impl Members {
-    pub fn new(id: usize, is_leader: bool, list: &HashMap<usize, String>) -> Self {
+    /// replicas start in view 1
+    pub fn new(id: usize, list: &HashMap<usize, String>) -> Self {
         Self {
             id,
-            is_leader: Arc::new(AtomicBool::new(is_leader)),
-            list: Arc::new(Mutex::new(list.clone())),
+            view: Arc::new(AtomicUsize::new(1)),
+            list: Arc::new(ArcSwap::from_pointee(MemberSet {
+                epoch: 0,
+                list: list.clone(),
+            })),
+            public_keys: Arc::new(HashMap::new()),
+            mac_keys: Arc::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_public_keys(mut self, public_keys: HashMap<usize, VerifyingKey>) -> Self {
+        self.public_keys = Arc::new(public_keys);
+        self
+    }
+
+    pub fn with_mac_keys(mut self, mac_keys: HashMap<usize, MacKey>) -> Self {
+        self.mac_keys = Arc::new(mac_keys);
+        self
+    }
+
+    /// the current member list, readers never see a change half applied
+    pub fn snapshot(&self) -> Arc<MemberSet> {
+        self.list.load_full()
+    }
+
+    /// install the list `change` derives from the current one, retried if another change
+    /// was installed meanwhile
+    fn update<F>(&self, change: F) -> Result<u64, ConsensusError>
+    where
+        F: Fn(&HashMap<usize, String>) -> Result<HashMap<usize, String>, ConsensusError>,
+    {
+        let mut current = self.list.load_full();
+        loop {
+            let next = Arc::new(MemberSet {
+                epoch: current.epoch + 1,
+                list: change(&current.list)?,
+            });
+            let prev = self.list.compare_and_swap(&current, next.clone());
+            if Arc::ptr_eq(&prev, &current) {
+                return Ok(next.epoch);
+            }
+            current = arc_swap::Guard::into_inner(prev);
         }
     }
 }
 
 impl Membership for Members {
     fn is_leader(&self) -> bool {
-        self.is_leader.clone().load(Ordering::SeqCst)
+        let view = self.view.load(Ordering::SeqCst);
+        view_change::primary(view, &self.members()) == Some(self.id)
     }
 
-    fn become_leader(&self) {
-        let _ = self.is_leader.clone().compare_exchange(
-            false,
-            true,
-            Ordering::SeqCst,
-            Ordering::SeqCst,
-        );
+    fn enter_view(&self, view: usize) {
+        self.view.store(view, Ordering::SeqCst);
     }
 
     fn local_id(&self) -> usize {
@@ -48,25 +107,42 @@ impl Membership for Members {
     }
 
     fn members(&self) -> HashMap<usize, String> {
-        if let Ok(lock) = self.list.clone().try_lock() {
-            return lock.clone();
-        }
-        HashMap::new()
+        self.list.load().list.clone()
     }
 
-    fn add_node(&self, id: usize, addr: String) {
-        if let Ok(mut lock) = self.list.clone().try_lock() {
-            if !lock.contains_key(&id) {
-                lock.insert(id, addr);
+    fn epoch(&self) -> u64 {
+        self.list.load().epoch
+    }
+
+    fn add_node(&self, id: usize, addr: String) -> Result<u64, ConsensusError> {
+        self.update(|list| {
+            if list.contains_key(&id) {
+                return Err(ConsensusError::MemberExists(id));
             }
-        }
+            let mut list = list.clone();
+            list.insert(id, addr.clone());
+            Ok(list)
+        })
     }
 
-    fn delete_node(&self, id: usize) {
-        if let Ok(mut lock) = self.list.clone().try_lock() {
-            if lock.contains_key(&id) {
-                lock.remove(&id);
+    fn delete_node(&self, id: usize) -> Result<u64, ConsensusError> {
+        self.update(|list| {
+            if !list.contains_key(&id) {
+                return Err(ConsensusError::NotAMember(id));
             }
-        }
+            // the cluster has to keep tolerating a faulty node
+            quorum::validate(list.len() - 1)?;
+            let mut list = list.clone();
+            list.remove(&id);
+            Ok(list)
+        })
+    }
+
+    fn public_key(&self, id: usize) -> Option<VerifyingKey> {
+        self.public_keys.get(&id).copied()
+    }
+
+    fn mac_key(&self, id: usize) -> Option<MacKey> {
+        self.mac_keys.get(&id).copied()
     }
 }
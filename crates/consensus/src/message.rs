@@ -10,7 +10,7 @@ pub struct Message {
     pub id: u64,
     #[prost(string, tag = "4")]
     pub digest: ::prost::alloc::string::String,
-    #[prost(oneof = "message::Payload", tags = "5, 6, 7, 8")]
+    #[prost(oneof = "message::Payload", tags = "5, 6, 7, 8, 9, 10, 11, 12")]
     pub payload: ::core::option::Option<message::Payload>,
 }
 /// Nested message and enum types in `Message`.
@@ -26,6 +26,14 @@ pub mod message {
         Prepare(super::Prepare),
         #[prost(message, tag = "8")]
         Commit(super::Commit),
+        #[prost(message, tag = "9")]
+        ViewChange(super::ViewChange),
+        #[prost(message, tag = "10")]
+        ViewChangeAck(super::ViewChangeAck),
+        #[prost(message, tag = "11")]
+        NewView(super::NewView),
+        #[prost(message, tag = "12")]
+        Checkpoint(super::Checkpoint),
     }
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
@@ -33,45 +41,246 @@ pub mod message {
 pub struct Request {
     #[prost(bytes = "vec", tag = "1")]
     pub payload: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint64, tag = "2")]
+    pub client: u64,
+    #[prost(uint64, tag = "3")]
+    pub timestamp: u64,
 }
+/// with mac authentication the normal-case messages carry an authenticator instead of
+/// a signature: a mac of the message for every replica, keyed by its id.
+/// the maps are generated as btree maps so that they re-encode to the same bytes
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct PrePrepare {
-    #[prost(bytes = "vec", tag = "1")]
-    pub payload: ::prost::alloc::vec::Vec<u8>,
     #[prost(bytes = "vec", tag = "2")]
     pub signature: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, repeated, tag = "3")]
+    pub requests: ::prost::alloc::vec::Vec<Request>,
+    #[prost(btree_map = "uint64, bytes", tag = "4")]
+    pub authenticator: ::prost::alloc::collections::BTreeMap<u64, ::prost::alloc::vec::Vec<u8>>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Prepare {
-    #[prost(bytes = "vec", tag = "1")]
-    pub payload: ::prost::alloc::vec::Vec<u8>,
     #[prost(bytes = "vec", tag = "2")]
     pub signature: ::prost::alloc::vec::Vec<u8>,
+    #[prost(btree_map = "uint64, bytes", tag = "3")]
+    pub authenticator: ::prost::alloc::collections::BTreeMap<u64, ::prost::alloc::vec::Vec<u8>>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Commit {
-    #[prost(bytes = "vec", tag = "1")]
-    pub payload: ::prost::alloc::vec::Vec<u8>,
     #[prost(bytes = "vec", tag = "2")]
     pub signature: ::prost::alloc::vec::Vec<u8>,
+    #[prost(btree_map = "uint64, bytes", tag = "3")]
+    pub authenticator: ::prost::alloc::collections::BTreeMap<u64, ::prost::alloc::vec::Vec<u8>>,
 }
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
-pub struct ViewChange {}
+pub struct Checkpoint {
+    #[prost(uint64, tag = "1")]
+    pub seq: u64,
+    #[prost(string, tag = "2")]
+    pub digest: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag = "3")]
+    pub signature: ::prost::alloc::vec::Vec<u8>,
+}
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
-pub struct ViewChangeAck {}
+pub struct PreparedCert {
+    #[prost(uint64, tag = "1")]
+    pub view: u64,
+    #[prost(uint64, tag = "2")]
+    pub seq: u64,
+    #[prost(string, tag = "3")]
+    pub digest: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "5")]
+    pub requests: ::prost::alloc::vec::Vec<Request>,
+    /// proof that the batch prepared: the pre-prepare of the primary and 2f matching prepares
+    #[prost(message, optional, tag = "6")]
+    pub pre_prepare: ::core::option::Option<Message>,
+    #[prost(message, repeated, tag = "7")]
+    pub prepares: ::prost::alloc::vec::Vec<Message>,
+}
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
-pub struct NewView {}
+pub struct ViewChange {
+    #[prost(uint64, tag = "1")]
+    pub new_view: u64,
+    #[prost(uint64, tag = "2")]
+    pub stable_checkpoint: u64,
+    #[prost(message, repeated, tag = "3")]
+    pub prepared: ::prost::alloc::vec::Vec<PreparedCert>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub signature: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, repeated, tag = "5")]
+    pub checkpoints: ::prost::alloc::vec::Vec<Message>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ViewChangeAck {
+    #[prost(uint64, tag = "1")]
+    pub new_view: u64,
+    #[prost(uint64, tag = "2")]
+    pub node: u64,
+    #[prost(bytes = "vec", tag = "3")]
+    pub signature: ::prost::alloc::vec::Vec<u8>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NewView {
+    #[prost(uint64, tag = "1")]
+    pub new_view: u64,
+    #[prost(message, repeated, tag = "2")]
+    pub view_changes: ::prost::alloc::vec::Vec<Message>,
+    #[prost(message, repeated, tag = "3")]
+    pub pre_prepares: ::prost::alloc::vec::Vec<Message>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub signature: ::prost::alloc::vec::Vec<u8>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Reply {
+    #[prost(uint64, tag = "1")]
+    pub view: u64,
+    #[prost(uint64, tag = "2")]
+    pub timestamp: u64,
+    #[prost(uint64, tag = "3")]
+    pub client: u64,
+    #[prost(uint64, tag = "4")]
+    pub replica: u64,
+    #[prost(bytes = "vec", tag = "5")]
+    pub result: ::prost::alloc::vec::Vec<u8>,
+}
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct MessageResponse {
     #[prost(string, tag = "1")]
     pub message: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub reply: ::core::option::Option<Reply>,
+    /// the request was not accepted, the client should back off and retry
+    #[prost(bool, tag = "3")]
+    pub busy: bool,
+}
+/// an entry of the write-ahead log
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WalRecord {
+    #[prost(oneof = "wal_record::Record", tags = "1, 2")]
+    pub record: ::core::option::Option<wal_record::Record>,
+}
+/// Nested message and enum types in `WalRecord`.
+pub mod wal_record {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Record {
+        #[prost(message, tag = "1")]
+        Message(super::Message),
+        #[prost(uint64, tag = "2")]
+        View(u64),
+    }
+}
+/// state of a replica at a checkpoint, enough to resume executing after it
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Snapshot {
+    #[prost(uint64, tag = "1")]
+    pub seq: u64,
+    #[prost(string, tag = "2")]
+    pub digest: ::prost::alloc::string::String,
+    #[prost(bytes = "vec", tag = "3")]
+    pub state: ::prost::alloc::vec::Vec<u8>,
+    #[prost(message, repeated, tag = "4")]
+    pub replies: ::prost::alloc::vec::Vec<Reply>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FetchCheckpointRequest {}
+/// a piece of the encoded snapshot of the stable checkpoint,
+/// the first chunk carries the checkpoint messages proving it
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SnapshotChunk {
+    #[prost(message, repeated, tag = "1")]
+    pub checkpoints: ::prost::alloc::vec::Vec<Message>,
+    #[prost(uint64, tag = "2")]
+    pub offset: u64,
+    #[prost(uint64, tag = "3")]
+    pub total: u64,
+    #[prost(bytes = "vec", tag = "4")]
+    pub data: ::prost::alloc::vec::Vec<u8>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CommittedRangeRequest {
+    #[prost(uint64, tag = "1")]
+    pub from: u64,
+    /// inclusive, 0 for everything committed
+    #[prost(uint64, tag = "2")]
+    pub to: u64,
+}
+/// a committed pre-prepare and the commit messages proving it
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CommittedEntry {
+    #[prost(message, optional, tag = "1")]
+    pub pre_prepare: ::core::option::Option<Message>,
+    #[prost(message, repeated, tag = "2")]
+    pub commits: ::prost::alloc::vec::Vec<Message>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CommittedRange {
+    #[prost(message, repeated, tag = "1")]
+    pub entries: ::prost::alloc::vec::Vec<CommittedEntry>,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NodeStatusRequest {}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NodeStatus {
+    #[prost(uint64, tag = "1")]
+    pub node: u64,
+    #[prost(uint64, tag = "2")]
+    pub view: u64,
+    #[prost(uint64, tag = "3")]
+    pub stable_checkpoint: u64,
+}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MetricsRequest {}
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PeerMetrics {
+    #[prost(uint64, tag = "1")]
+    pub node: u64,
+    /// idle, connected or failed
+    #[prost(string, tag = "2")]
+    pub state: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "3")]
+    pub failures: u32,
+    /// broadcast messages dropped because its queue was full
+    #[prost(uint64, tag = "4")]
+    pub dropped: u64,
+}
+/// counters of a replica, rejected and dropped messages are counted per reason
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MetricsReport {
+    #[prost(uint64, tag = "1")]
+    pub node: u64,
+    #[prost(uint64, tag = "2")]
+    pub view: u64,
+    #[prost(uint64, tag = "3")]
+    pub stable_checkpoint: u64,
+    #[prost(map = "string, uint64", tag = "4")]
+    pub rejected: ::std::collections::HashMap<::prost::alloc::string::String, u64>,
+    #[prost(map = "string, uint64", tag = "5")]
+    pub dropped: ::std::collections::HashMap<::prost::alloc::string::String, u64>,
+    #[prost(message, repeated, tag = "6")]
+    pub peers: ::prost::alloc::vec::Vec<PeerMetrics>,
 }
 /// Generated client implementations.
 pub mod pbft_client {
@@ -170,6 +379,98 @@ pub mod pbft_client {
                 .insert(GrpcMethod::new("message.Pbft", "SendMessage"));
             self.inner.unary(req, path, codec).await
         }
+        /// one long-lived stream of protocol messages between a pair of replicas
+        pub async fn message_stream(
+            &mut self,
+            request: impl tonic::IntoStreamingRequest<Message = super::Message>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::MessageResponse>>,
+            tonic::Status,
+        > {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/message.Pbft/MessageStream");
+            let mut req = request.into_streaming_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("message.Pbft", "MessageStream"));
+            self.inner.streaming(req, path, codec).await
+        }
+        pub async fn get_status(
+            &mut self,
+            request: impl tonic::IntoRequest<super::NodeStatusRequest>,
+        ) -> std::result::Result<tonic::Response<super::NodeStatus>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/message.Pbft/GetStatus");
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("message.Pbft", "GetStatus"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn get_metrics(
+            &mut self,
+            request: impl tonic::IntoRequest<super::MetricsRequest>,
+        ) -> std::result::Result<tonic::Response<super::MetricsReport>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/message.Pbft/GetMetrics");
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("message.Pbft", "GetMetrics"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn fetch_checkpoint(
+            &mut self,
+            request: impl tonic::IntoRequest<super::FetchCheckpointRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::SnapshotChunk>>,
+            tonic::Status,
+        > {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/message.Pbft/FetchCheckpoint");
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("message.Pbft", "FetchCheckpoint"));
+            self.inner.server_streaming(req, path, codec).await
+        }
+        pub async fn fetch_committed_range(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CommittedRangeRequest>,
+        ) -> std::result::Result<tonic::Response<super::CommittedRange>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/message.Pbft/FetchCommittedRange");
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("message.Pbft", "FetchCommittedRange"));
+            self.inner.unary(req, path, codec).await
+        }
     }
 }
 /// Generated server implementations.
@@ -183,6 +484,37 @@ pub mod pbft_server {
             &self,
             request: tonic::Request<super::Message>,
         ) -> std::result::Result<tonic::Response<super::MessageResponse>, tonic::Status>;
+        /// Server streaming response type for the MessageStream method.
+        type MessageStreamStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::MessageResponse, tonic::Status>,
+            > + Send
+            + 'static;
+        /// one long-lived stream of protocol messages between a pair of replicas
+        async fn message_stream(
+            &self,
+            request: tonic::Request<tonic::Streaming<super::Message>>,
+        ) -> std::result::Result<tonic::Response<Self::MessageStreamStream>, tonic::Status>;
+        async fn get_status(
+            &self,
+            request: tonic::Request<super::NodeStatusRequest>,
+        ) -> std::result::Result<tonic::Response<super::NodeStatus>, tonic::Status>;
+        async fn get_metrics(
+            &self,
+            request: tonic::Request<super::MetricsRequest>,
+        ) -> std::result::Result<tonic::Response<super::MetricsReport>, tonic::Status>;
+        /// Server streaming response type for the FetchCheckpoint method.
+        type FetchCheckpointStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::SnapshotChunk, tonic::Status>,
+            > + Send
+            + 'static;
+        async fn fetch_checkpoint(
+            &self,
+            request: tonic::Request<super::FetchCheckpointRequest>,
+        ) -> std::result::Result<tonic::Response<Self::FetchCheckpointStream>, tonic::Status>;
+        async fn fetch_committed_range(
+            &self,
+            request: tonic::Request<super::CommittedRangeRequest>,
+        ) -> std::result::Result<tonic::Response<super::CommittedRange>, tonic::Status>;
     }
     #[derive(Debug)]
     pub struct PbftServer<T: Pbft> {
@@ -299,6 +631,210 @@ pub mod pbft_server {
                     };
                     Box::pin(fut)
                 }
+                "/message.Pbft/MessageStream" => {
+                    #[allow(non_camel_case_types)]
+                    struct MessageStreamSvc<T: Pbft>(pub Arc<T>);
+                    impl<T: Pbft> tonic::server::StreamingService<super::Message> for MessageStreamSvc<T> {
+                        type Response = super::MessageResponse;
+                        type ResponseStream = T::MessageStreamStream;
+                        type Future =
+                            BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<tonic::Streaming<super::Message>>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut =
+                                async move { <T as Pbft>::message_stream(&inner, request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = MessageStreamSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/message.Pbft/GetStatus" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetStatusSvc<T: Pbft>(pub Arc<T>);
+                    impl<T: Pbft> tonic::server::UnaryService<super::NodeStatusRequest> for GetStatusSvc<T> {
+                        type Response = super::NodeStatus;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::NodeStatusRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move { <T as Pbft>::get_status(&inner, request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = GetStatusSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/message.Pbft/GetMetrics" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetMetricsSvc<T: Pbft>(pub Arc<T>);
+                    impl<T: Pbft> tonic::server::UnaryService<super::MetricsRequest> for GetMetricsSvc<T> {
+                        type Response = super::MetricsReport;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::MetricsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut =
+                                async move { <T as Pbft>::get_metrics(&inner, request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = GetMetricsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/message.Pbft/FetchCheckpoint" => {
+                    #[allow(non_camel_case_types)]
+                    struct FetchCheckpointSvc<T: Pbft>(pub Arc<T>);
+                    impl<T: Pbft>
+                        tonic::server::ServerStreamingService<super::FetchCheckpointRequest>
+                        for FetchCheckpointSvc<T>
+                    {
+                        type Response = super::SnapshotChunk;
+                        type ResponseStream = T::FetchCheckpointStream;
+                        type Future =
+                            BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::FetchCheckpointRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut =
+                                async move { <T as Pbft>::fetch_checkpoint(&inner, request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = FetchCheckpointSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/message.Pbft/FetchCommittedRange" => {
+                    #[allow(non_camel_case_types)]
+                    struct FetchCommittedRangeSvc<T: Pbft>(pub Arc<T>);
+                    impl<T: Pbft> tonic::server::UnaryService<super::CommittedRangeRequest>
+                        for FetchCommittedRangeSvc<T>
+                    {
+                        type Response = super::CommittedRange;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::CommittedRangeRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Pbft>::fetch_committed_range(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = FetchCommittedRangeSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => Box::pin(async move {
                     Ok(http::Response::builder()
                         .status(200)
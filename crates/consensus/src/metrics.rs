@@ -0,0 +1,153 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejectReason {
+    UnknownSender = 0,
+    InvalidSignature = 1,
+    DigestMismatch = 2,
+    ViewMismatch = 3,
+    OutOfWindow = 4,
+    InvalidViewChange = 5,
+    InvalidNewView = 6,
+    InvalidAuthenticator = 7,
+    InvalidPrePrepare = 8,
+}
+
+impl RejectReason {
+    pub const ALL: [RejectReason; 9] = [
+        RejectReason::UnknownSender,
+        RejectReason::InvalidSignature,
+        RejectReason::DigestMismatch,
+        RejectReason::ViewMismatch,
+        RejectReason::OutOfWindow,
+        RejectReason::InvalidViewChange,
+        RejectReason::InvalidNewView,
+        RejectReason::InvalidAuthenticator,
+        RejectReason::InvalidPrePrepare,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::UnknownSender => "unknown_sender",
+            RejectReason::InvalidSignature => "invalid_signature",
+            RejectReason::DigestMismatch => "digest_mismatch",
+            RejectReason::ViewMismatch => "view_mismatch",
+            RejectReason::OutOfWindow => "out_of_window",
+            RejectReason::InvalidViewChange => "invalid_view_change",
+            RejectReason::InvalidNewView => "invalid_new_view",
+            RejectReason::InvalidAuthenticator => "invalid_authenticator",
+            RejectReason::InvalidPrePrepare => "invalid_pre_prepare",
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// why a well-formed message was deliberately not processed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropReason {
+    /// the pool queue was full, the client was told to retry
+    QueueFull = 0,
+    /// the sequence window was full, the client was told to retry
+    WindowFull = 1,
+    /// a view change is in progress
+    ViewChanging = 2,
+    /// a checkpoint at or below the stable checkpoint
+    StaleCheckpoint = 3,
+    /// the message could not be logged
+    NotPersisted = 4,
+    /// a checkpoint at or above the high watermark, kept to prove that this replica fell behind
+    CheckpointAhead = 5,
+}
+
+impl DropReason {
+    pub const ALL: [DropReason; 6] = [
+        DropReason::QueueFull,
+        DropReason::WindowFull,
+        DropReason::ViewChanging,
+        DropReason::StaleCheckpoint,
+        DropReason::NotPersisted,
+        DropReason::CheckpointAhead,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DropReason::QueueFull => "queue_full",
+            DropReason::WindowFull => "window_full",
+            DropReason::ViewChanging => "view_changing",
+            DropReason::StaleCheckpoint => "stale_checkpoint",
+            DropReason::NotPersisted => "not_persisted",
+            DropReason::CheckpointAhead => "checkpoint_ahead_of_window",
+        }
+    }
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// counters and gauges shared by the consensus tasks
+#[derive(Default)]
+pub struct Metrics {
+    rejected: [AtomicU64; RejectReason::ALL.len()],
+    dropped: [AtomicU64; DropReason::ALL.len()],
+    view: AtomicU64,
+    stable_checkpoint: AtomicU64,
+}
+
+impl Metrics {
+    /// count a rejected message, returns the total for this reason
+    pub fn reject(&self, reason: RejectReason) -> u64 {
+        self.rejected[reason as usize].fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn rejected(&self, reason: RejectReason) -> u64 {
+        self.rejected[reason as usize].load(Ordering::Relaxed)
+    }
+
+    /// count a dropped message, returns the total for this reason
+    pub fn drop_message(&self, reason: DropReason) -> u64 {
+        self.dropped[reason as usize].fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn dropped(&self, reason: DropReason) -> u64 {
+        self.dropped[reason as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_all(&self) -> Vec<(DropReason, u64)> {
+        DropReason::ALL
+            .iter()
+            .map(|reason| (*reason, self.dropped(*reason)))
+            .collect()
+    }
+
+    pub fn set_view(&self, view: u64) {
+        self.view.store(view, Ordering::Relaxed);
+    }
+
+    pub fn view(&self) -> u64 {
+        self.view.load(Ordering::Relaxed)
+    }
+
+    pub fn set_stable_checkpoint(&self, seq: u64) {
+        self.stable_checkpoint.store(seq, Ordering::Relaxed);
+    }
+
+    pub fn stable_checkpoint(&self) -> u64 {
+        self.stable_checkpoint.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected_all(&self) -> Vec<(RejectReason, u64)> {
+        RejectReason::ALL
+            .iter()
+            .map(|reason| (*reason, self.rejected(*reason)))
+            .collect()
+    }
+}
@@ -0,0 +1,466 @@
+use crate::error::ConsensusError;
+use crate::message::{
+    pbft_client::PbftClient, CommittedRange, CommittedRangeRequest, FetchCheckpointRequest,
+    Message, MessageResponse, NodeStatus, NodeStatusRequest, SnapshotChunk,
+};
+use crate::tls::{self, TlsOptions};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, Mutex, Notify},
+    task::JoinHandle,
+    time::{self, Instant},
+};
+use tonic::{
+    codegen::tokio_stream::wrappers::ReceiverStream,
+    transport::{Channel, Endpoint},
+    Code, Streaming,
+};
+use tracing::{debug, warn};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(1000);
+// a failed peer is not retried before its backoff, doubled for every consecutive failure
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+pub const DEFAULT_QUEUE_CAPACITY: usize = 256;
+// messages buffered between a peer worker and its stream
+const STREAM_BUFFER: usize = 64;
+
+/// how broadcast messages travel to a peer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    /// one `SendMessage` call per message
+    Unary,
+    /// one long-lived `MessageStream` per peer, unary calls for peers that do not serve it
+    Stream,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// the channel is created, nothing was sent over it yet
+    Idle,
+    Connected,
+    /// the last `failures` calls failed, the peer is not retried before `retry_at`
+    Failed {
+        failures: u32,
+        retry_at: Instant,
+    },
+}
+
+impl ConnectionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionState::Idle => "idle",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Failed { .. } => "failed",
+        }
+    }
+}
+
+struct Peer {
+    addr: String,
+    channel: Option<Channel>,
+    state: ConnectionState,
+    // the peer answered that it does not serve streams, messages go to it by unary calls
+    unary: bool,
+}
+
+impl Peer {
+    fn fail(&mut self) {
+        let failures = match self.state {
+            ConnectionState::Failed { failures, .. } => failures.saturating_add(1),
+            _ => 1,
+        };
+        let backoff = MIN_BACKOFF
+            .saturating_mul(2u32.saturating_pow(failures - 1))
+            .min(MAX_BACKOFF);
+        self.channel = None;
+        self.state = ConnectionState::Failed {
+            failures,
+            retry_at: Instant::now() + backoff,
+        };
+    }
+}
+
+/// the sending half of a stream to a peer and the task reading its responses
+struct OutStream {
+    sender: mpsc::Sender<Message>,
+    reader: JoinHandle<()>,
+}
+
+impl OutStream {
+    fn is_closed(&self) -> bool {
+        self.sender.is_closed() || self.reader.is_finished()
+    }
+}
+
+impl Drop for OutStream {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// bounded queue of the messages broadcast to one peer
+struct Outbox {
+    queue: std::sync::Mutex<VecDeque<Message>>,
+    ready: Notify,
+    capacity: usize,
+    dropped: AtomicU64,
+}
+
+impl Outbox {
+    /// a peer that falls behind loses its oldest messages, the broadcaster never waits
+    fn push(&self, msg: Message) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.len() >= self.capacity {
+            queue.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        queue.push_back(msg);
+        drop(queue);
+        self.ready.notify_one();
+    }
+
+    fn pop(&self) -> Option<Message> {
+        self.queue.lock().unwrap().pop_front()
+    }
+}
+
+/// the outbox of a peer and the worker draining it
+struct Queue {
+    addr: String,
+    outbox: Arc<Outbox>,
+    worker: JoinHandle<()>,
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        self.worker.abort();
+    }
+}
+
+/// long-lived channels to the other replicas, keyed by node id
+pub struct Peers {
+    peers: Mutex<HashMap<usize, Peer>>,
+    queues: Mutex<HashMap<usize, Queue>>,
+    queue_capacity: usize,
+    transport: Transport,
+    tls: Option<TlsOptions>,
+}
+
+impl Default for Peers {
+    fn default() -> Self {
+        Self::new(DEFAULT_QUEUE_CAPACITY, Transport::Stream)
+    }
+}
+
+impl Peers {
+    /// at most `queue_capacity` broadcast messages wait for each peer
+    pub fn new(queue_capacity: usize, transport: Transport) -> Self {
+        Self {
+            peers: Mutex::new(HashMap::new()),
+            queues: Mutex::new(HashMap::new()),
+            queue_capacity: queue_capacity.max(1),
+            transport,
+            tls: None,
+        }
+    }
+
+    /// connect to the peers over mutual tls
+    pub fn with_tls(mut self, tls: TlsOptions) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// connection state of every peer a channel was asked for
+    pub async fn states(&self) -> HashMap<usize, ConnectionState> {
+        self.peers
+            .lock()
+            .await
+            .iter()
+            .map(|(id, peer)| (*id, peer.state))
+            .collect()
+    }
+
+    /// broadcast messages dropped for every peer because its queue was full
+    pub async fn dropped(&self) -> HashMap<usize, u64> {
+        self.queues
+            .lock()
+            .await
+            .iter()
+            .map(|(id, queue)| (*id, queue.outbox.dropped.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// drop the channels and queues of peers that are no longer members
+    pub async fn retain(&self, members: &HashMap<usize, String>) {
+        self.peers.lock().await.retain(|id, peer| {
+            let keep = members.get(id).is_some_and(|addr| *addr == peer.addr);
+            if !keep {
+                debug!("drop channel of node{} at addr {}", id, peer.addr);
+            }
+            keep
+        });
+        self.queues
+            .lock()
+            .await
+            .retain(|id, queue| members.get(id).is_some_and(|addr| *addr == queue.addr));
+    }
+
+    pub async fn send(
+        &self,
+        id: usize,
+        addr: &str,
+        msg: Message,
+    ) -> Result<MessageResponse, ConsensusError> {
+        let mut client = self.client(id, addr).await?;
+        let resp = client.send_message(tonic::Request::new(msg)).await;
+        self.report(id, resp).await
+    }
+
+    pub async fn status(&self, id: usize, addr: &str) -> Result<NodeStatus, ConsensusError> {
+        let mut client = self.client(id, addr).await?;
+        let resp = client
+            .get_status(tonic::Request::new(NodeStatusRequest {}))
+            .await;
+        self.report(id, resp).await
+    }
+
+    pub async fn fetch_checkpoint(
+        &self,
+        id: usize,
+        addr: &str,
+    ) -> Result<Streaming<SnapshotChunk>, ConsensusError> {
+        let mut client = self.client(id, addr).await?;
+        let resp = client
+            .fetch_checkpoint(tonic::Request::new(FetchCheckpointRequest {}))
+            .await;
+        self.report(id, resp).await
+    }
+
+    pub async fn fetch_committed_range(
+        &self,
+        id: usize,
+        addr: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<CommittedRange, ConsensusError> {
+        let mut client = self.client(id, addr).await?;
+        let resp = client
+            .fetch_committed_range(tonic::Request::new(CommittedRangeRequest { from, to }))
+            .await;
+        self.report(id, resp).await
+    }
+
+    /// queue `msg` for every member but `local`, each peer is sent to by its own worker
+    pub async fn broadcast(
+        self: &Arc<Self>,
+        local: usize,
+        list: HashMap<usize, String>,
+        msg: Message,
+    ) {
+        self.retain(&list).await;
+        let mut queues = self.queues.lock().await;
+        for (id, addr) in list {
+            if id == local {
+                continue;
+            }
+            let queue = queues.entry(id).or_insert_with(|| {
+                let outbox = Arc::new(Outbox {
+                    queue: std::sync::Mutex::new(VecDeque::new()),
+                    ready: Notify::new(),
+                    capacity: self.queue_capacity,
+                    dropped: AtomicU64::new(0),
+                });
+                let worker = tokio::spawn(drain(
+                    Arc::downgrade(self),
+                    id,
+                    addr.clone(),
+                    outbox.clone(),
+                ));
+                Queue {
+                    addr,
+                    outbox,
+                    worker,
+                }
+            });
+            queue.outbox.push(msg.clone());
+        }
+    }
+
+    /// send `msg` over the stream to `id`, opening the stream first if it is closed
+    async fn stream(
+        &self,
+        id: usize,
+        addr: &str,
+        stream: &mut Option<OutStream>,
+        msg: Message,
+    ) -> Result<(), ConsensusError> {
+        if self.unary(id).await {
+            return self.send(id, addr, msg).await.map(|_| ());
+        }
+        if stream.as_ref().is_some_and(|s| s.is_closed()) {
+            debug!("stream to node{} closed", id);
+            *stream = None;
+            self.fail(id).await;
+            self.backoff(id).await;
+        }
+        let out = match stream {
+            Some(out) => out,
+            None => {
+                let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+                let mut client = self.client(id, addr).await?;
+                let resp = client.message_stream(ReceiverStream::new(receiver)).await;
+                if let Err(ref status) = resp {
+                    if status.code() == Code::Unimplemented {
+                        debug!("node{} does not serve streams, fall back to unary", id);
+                        if let Some(peer) = self.peers.lock().await.get_mut(&id) {
+                            peer.unary = true;
+                        }
+                        return self.send(id, addr, msg).await.map(|_| ());
+                    }
+                }
+                let mut responses = self.report(id, resp).await?;
+                // the responses are only drained, a broken stream ends the reader
+                let reader = tokio::spawn(async move {
+                    loop {
+                        match responses.message().await {
+                            Ok(Some(resp)) => {
+                                debug!("stream response from node{}: {}", id, resp.message)
+                            }
+                            Ok(None) => break,
+                            Err(status) => {
+                                warn!("stream to node{} err: {}", id, status);
+                                break;
+                            }
+                        }
+                    }
+                });
+                stream.insert(OutStream { sender, reader })
+            }
+        };
+        out.sender
+            .send(msg)
+            .await
+            .map_err(|_| ConsensusError::PeerUnavailable(id))
+    }
+
+    /// whether `id` was found not to serve streams
+    async fn unary(&self, id: usize) -> bool {
+        self.peers
+            .lock()
+            .await
+            .get(&id)
+            .is_some_and(|peer| peer.unary)
+    }
+
+    /// wait out the backoff of a failed peer
+    async fn backoff(&self, id: usize) {
+        let state = self.peers.lock().await.get(&id).map(|peer| peer.state);
+        if let Some(ConnectionState::Failed { retry_at, .. }) = state {
+            time::sleep_until(retry_at).await;
+        }
+    }
+
+    /// the channel to `id`, created lazily. a failed peer is not retried before its backoff
+    async fn client(&self, id: usize, addr: &str) -> Result<PbftClient<Channel>, ConsensusError> {
+        let mut peers = self.peers.lock().await;
+        let peer = peers.entry(id).or_insert_with(|| Peer {
+            addr: addr.to_string(),
+            channel: None,
+            state: ConnectionState::Idle,
+            unary: false,
+        });
+        // another replica may serve streams at the new address
+        if peer.addr != addr {
+            peer.addr = addr.to_string();
+            peer.channel = None;
+            peer.unary = false;
+        }
+        if let Some(ref channel) = peer.channel {
+            return Ok(PbftClient::new(channel.clone()));
+        }
+        if let ConnectionState::Failed { retry_at, .. } = peer.state {
+            if Instant::now() < retry_at {
+                return Err(ConsensusError::PeerUnavailable(id));
+            }
+        }
+        let mut endpoint =
+            Endpoint::from_shared(addr.to_string())?.connect_timeout(CONNECT_TIMEOUT);
+        if let Some(ref options) = self.tls {
+            endpoint = endpoint.tls_config(tls::client_config(options, id)?)?;
+        }
+        let channel = endpoint.connect_lazy();
+        peer.channel = Some(channel.clone());
+        Ok(PbftClient::new(channel))
+    }
+
+    /// drop the channel to `id` and back off before dialing it again
+    async fn fail(&self, id: usize) {
+        if let Some(peer) = self.peers.lock().await.get_mut(&id) {
+            peer.fail();
+        }
+    }
+
+    /// track the state of the channel to `id` from the outcome of a call over it
+    async fn report<T>(
+        &self,
+        id: usize,
+        resp: Result<tonic::Response<T>, tonic::Status>,
+    ) -> Result<T, ConsensusError> {
+        let mut peers = self.peers.lock().await;
+        let peer = peers.get_mut(&id);
+        match resp {
+            Ok(resp) => {
+                if let Some(peer) = peer {
+                    peer.state = ConnectionState::Connected;
+                }
+                Ok(resp.into_inner())
+            }
+            Err(status) => {
+                // only transport failures say something about the connection
+                if let (Some(peer), Code::Unavailable | Code::Unknown) = (peer, status.code()) {
+                    peer.fail();
+                }
+                Err(status.into())
+            }
+        }
+    }
+}
+
+/// send the queued messages to `id` in order until the peers are dropped
+async fn drain(peers: Weak<Peers>, id: usize, addr: String, outbox: Arc<Outbox>) {
+    let mut stream = None;
+    loop {
+        let msg = match outbox.pop() {
+            Some(msg) => msg,
+            None => {
+                outbox.ready.notified().await;
+                continue;
+            }
+        };
+        let Some(peers) = peers.upgrade() else {
+            return;
+        };
+        // messages queued meanwhile replace the oldest ones instead of piling up
+        peers.backoff(id).await;
+        let sent = match peers.transport {
+            Transport::Unary => peers.send(id, &addr, msg).await.map(|_| ()),
+            Transport::Stream => peers.stream(id, &addr, &mut stream, msg).await,
+        };
+        match sent {
+            Ok(()) => {
+                debug!("send msg to node{} success", id);
+            }
+            Err(err) => {
+                warn!("send msg to node{} at addr {} err: {}", id, addr, err);
+            }
+        }
+    }
+}
@@ -1,20 +1,56 @@
+use crate::checkpoint;
+use crate::crypto;
+use crate::digest::{batch_digest, request_digest};
 use crate::event::Event;
 use crate::members::Membership;
-use crate::message::{message::Payload, Commit, Message, PrePrepare, Prepare};
+use crate::message::{
+    message::Payload, wal_record::Record, Checkpoint, Commit, CommittedEntry, Message, NewView,
+    PrePrepare, Prepare, PreparedCert, Request, ViewChange, ViewChangeAck, WalRecord,
+};
+use crate::metrics::{DropReason, Metrics, RejectReason};
+use crate::quorum;
+use crate::reply::Replies;
+use crate::server::Options;
+use crate::transfer::Store;
+use crate::view_change;
+use crate::wal::Wal;
+use ed25519_dalek::SigningKey;
 use std::{
-    collections::{hash_map, HashMap},
+    collections::{hash_map, BTreeMap, HashMap, HashSet},
     sync::Arc,
+    time::Duration,
 };
-use tokio::sync::{
-    mpsc::{Receiver, Sender},
-    Mutex,
+use tokio::{
+    select,
+    sync::mpsc::{Receiver, Sender, UnboundedReceiver},
+    time::{self, Instant},
 };
 use tracing::{debug, error, info, warn};
 
+const TIMER_TICK: Duration = Duration::from_millis(100);
+const MIN_TIMER_TICK: Duration = Duration::from_millis(1);
+
+#[derive(Default)]
 struct SeqMessage {
+    digest: String,
     pre_prepare: HashMap<usize, PrePrepare>,
-    prepare: HashMap<usize, Prepare>,
-    commit: HashMap<usize, Commit>,
+    // votes are counted per digest, so conflicting digests never reach a quorum together
+    prepare: HashMap<String, HashMap<usize, Prepare>>,
+    commit: HashMap<String, HashMap<usize, Commit>>,
+    commited_local: bool,
+    // prepared before a restart, the prepares of the others are not logged
+    prepared_local: bool,
+}
+
+impl SeqMessage {
+    fn clear(&mut self) {
+        self.digest.clear();
+        self.pre_prepare.clear();
+        self.prepare.clear();
+        self.commit.clear();
+        self.commited_local = false;
+        self.prepared_local = false;
+    }
 }
 
 pub struct Pool<T: Membership> {
@@ -25,48 +61,164 @@ pub struct Pool<T: Membership> {
     capacity: usize,
     queue: Vec<SeqMessage>,
     start: usize,
+    next_seq: usize,
+
+    // requests the primary has not ordered yet
+    batch: Vec<Request>,
+    batch_bytes: usize,
+    batch_deadline: Option<Instant>,
+    max_batch_size: usize,
+    max_batch_bytes: usize,
+    batch_delay: Duration,
+
+    // checkpoint
+    checkpoints: HashMap<usize, HashMap<usize, Message>>,
+    stable_proof: Vec<Message>,
+    // the latest checkpoint of each member past the high watermark
+    checkpoints_ahead: HashMap<usize, Message>,
+
+    // view change
+    view_changing: bool,
+    pending_view: usize,
+    view_changes: HashMap<usize, HashMap<usize, Message>>,
+    view_change_acks: HashMap<usize, HashMap<usize, HashSet<usize>>>,
+
+    // timers
+    request_timeout: Duration,
+    view_change_timeout: Duration,
+    requests: HashMap<String, Instant>,
+    // since when the window is full as seen by a backup, the primary answers busy meanwhile
+    window_full_since: Option<Instant>,
+    view_change_deadline: Option<Instant>,
+    view_change_attempts: u32,
+
+    signing_key: Option<SigningKey>,
+    // pre-prepare, prepare and commit carry mac authenticators instead of signatures
+    authenticators: bool,
+    metrics: Arc<Metrics>,
+    replies: Arc<Replies>,
+    store: Arc<Store>,
+    wal: Option<Wal>,
 
     event_sender: Sender<Event>,
 }
 
+/// the task owning the pool, every message and timer tick is handled in turn
 pub struct RequestHandler<T: Membership> {
-    message_pool: Arc<Mutex<Pool<T>>>,
+    message_pool: Pool<T>,
     receiver: Receiver<Message>,
+    // own checkpoints from the event handler
+    checkpoints: UnboundedReceiver<Message>,
+    tick: Duration,
 }
 
 impl<T: Membership> RequestHandler<T> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         member: Arc<T>,
         receiver: Receiver<Message>,
-        capacity: usize,
+        checkpoints: UnboundedReceiver<Message>,
         sender: Sender<Event>,
+        options: &Options,
+        metrics: Arc<Metrics>,
+        replies: Arc<Replies>,
+        store: Arc<Store>,
     ) -> Self {
+        // sequences in flight live in (low, high) watermarks, high = low + window
+        let capacity = options.window;
+        metrics.set_view(1);
         let mut b: Vec<SeqMessage> = Vec::new();
         for _ in 0..capacity {
-            b.push(SeqMessage {
-                pre_prepare: HashMap::new(),
-                prepare: HashMap::new(),
-                commit: HashMap::new(),
-            })
+            b.push(SeqMessage::default())
         }
         Self {
             receiver,
-            message_pool: Arc::new(Mutex::new(Pool {
+            checkpoints,
+            // batches must not wait much longer than their delay
+            tick: TIMER_TICK.min(options.batch_delay).max(MIN_TIMER_TICK),
+            message_pool: Pool {
                 member,
                 view: 1,
                 stable_checkpoint: 0,
                 capacity,
                 queue: b,
                 start: 0,
+                next_seq: 0,
+                batch: Vec::new(),
+                batch_bytes: 0,
+                batch_deadline: None,
+                max_batch_size: options.batch_size.max(1),
+                max_batch_bytes: options.batch_bytes,
+                batch_delay: options.batch_delay,
+                checkpoints: HashMap::new(),
+                stable_proof: Vec::new(),
+                checkpoints_ahead: HashMap::new(),
+                view_changing: false,
+                pending_view: 1,
+                view_changes: HashMap::new(),
+                view_change_acks: HashMap::new(),
+                request_timeout: options.request_timeout,
+                view_change_timeout: options.view_change_timeout,
+                requests: HashMap::new(),
+                window_full_since: None,
+                view_change_deadline: None,
+                view_change_attempts: 0,
+                signing_key: options.signing_key.clone(),
+                authenticators: options.authenticators,
+                metrics,
+                replies,
+                store,
+                wal: None,
                 event_sender: sender,
-            })),
+            },
+        }
+    }
+
+    /// replay the records of `wal` into the pool, then log everything the pool does to it
+    pub async fn recover(&mut self, wal: Wal, records: Vec<WalRecord>) {
+        let pool = &mut self.message_pool;
+        let count = records.len();
+        for record in records {
+            pool.replay(record).await;
+        }
+        pool.wal = Some(wal);
+        pool.compact_wal();
+        pool.metrics.set_view(pool.view as u64);
+        info!(
+            "[WAL] replayed {} records. view:{}, stable checkpoint:{}, next sequence:{}",
+            count, pool.view, pool.stable_checkpoint, pool.next_seq
+        );
+        if pool.view_changing {
+            pool.resume_view_change().await;
+        }
+    }
+
+    /// move to a view the other replicas already reached while this one was down
+    pub async fn join_view(&mut self, view: usize) {
+        let pool = &mut self.message_pool;
+        if view <= pool.view {
+            return;
         }
+        info!("[RECOVERY] join view:{} from view:{}", view, pool.view);
+        pool.enter_view(view, Vec::new()).await;
     }
 
     pub async fn start(&mut self) {
-        while let Some(message) = self.receiver.recv().await {
-            if let Ok(mut lock) = self.message_pool.clone().try_lock() {
-                lock.add(message).await;
+        let mut tick = time::interval(self.tick);
+        loop {
+            select! {
+                message = self.receiver.recv() => {
+                    let Some(message) = message else {
+                        break;
+                    };
+                    self.message_pool.add(message).await;
+                }
+                Some(checkpoint) = self.checkpoints.recv() => {
+                    self.message_pool.add(checkpoint).await;
+                }
+                _ = tick.tick() => {
+                    self.message_pool.check_timers().await;
+                }
             }
         }
     }
@@ -74,61 +226,84 @@ impl<T: Membership> RequestHandler<T> {
 
 impl<T: Membership> Pool<T> {
     async fn add(&mut self, m: Message) {
+        if let Err(reason) = self.authenticate(&m) {
+            self.reject(reason, &m);
+            return;
+        }
+
+        match m.payload {
+            Some(Payload::ViewChange(ref view_change)) => {
+                let view_change = view_change.clone();
+                return self.on_view_change(m, view_change).await;
+            }
+            Some(Payload::ViewChangeAck(ref ack)) => {
+                let ack = ack.clone();
+                return self.on_view_change_ack(m.id as usize, ack).await;
+            }
+            Some(Payload::NewView(ref new_view)) => {
+                let new_view = new_view.clone();
+                if let Err(reason) = self.on_new_view(m.id as usize, new_view).await {
+                    self.reject(reason, &m);
+                }
+                return;
+            }
+            Some(Payload::Checkpoint(ref cp)) => {
+                let cp = cp.clone();
+                return self.on_checkpoint(m, cp).await;
+            }
+            Some(Payload::Request(ref request)) => {
+                let request = request.clone();
+                return self.on_request(m, request).await;
+            }
+            _ => {}
+        }
+
+        if self.view_changing {
+            self.drop_message(DropReason::ViewChanging, &m);
+            return;
+        }
+
         let m_view = m.view as usize;
         let m_seq = m.seq as usize;
 
-        if !self.view_seq_check(m_view, m_seq) {
+        if let Err(reason) = self.view_seq_check(m_view, m_seq) {
+            self.reject(reason, &m);
             return;
         }
 
         let index = self.index_in_queue(m_seq);
 
         match m.payload {
-            Some(Payload::Request(ref request)) => {
-                debug!(
-                    "[REQUEST] received request. view:{}, sequence:{}",
-                    m_view, m_seq
-                );
-                if self.member.is_leader() {
-                    info!(
-                        "[REQUEST]is leader, broadcast pre-prepare message. view:{}, sequence:{}",
-                        m_view, m_seq
-                    );
-                    let pre_prepare = PrePrepare {
-                        payload: request.clone().payload,
-                        signature: vec![],
-                    };
-                    let _ = self.queue[index]
-                        .pre_prepare
-                        .insert(m.id as usize, pre_prepare);
-
-                    self.event(Event::new_broadcast(
-                        self.member.local_id() as u64,
-                        m.clone(),
-                    ))
-                    .await;
-                } else {
-                    debug!("not leader, do nothing");
-                }
-            }
             Some(Payload::PrePrepare(ref pre_prepare)) => {
                 debug!(
                     "[PRE-PREPARE] received pre-prepare message from node{}. view:{}, sequence:{}",
                     m.id, m_view, m_seq
                 );
+                // only the primary of the view assigns sequence numbers
+                if self.primary(m_view) != Some(m.id as usize) {
+                    self.reject(RejectReason::InvalidPrePrepare, &m);
+                    return;
+                }
+                if m.digest != batch_digest(&pre_prepare.requests) {
+                    self.reject(RejectReason::DigestMismatch, &m);
+                    return;
+                }
                 if !self.is_pre_prepared(index) {
-                    let _ = self.queue[index]
-                        .pre_prepare
-                        .insert(m.id as usize, pre_prepare.clone());
+                    if !self.persist(&m) {
+                        return;
+                    }
+                    let slot = &mut self.queue[index];
+                    slot.digest = m.digest.clone();
+                    let _ = slot.pre_prepare.insert(m.id as usize, pre_prepare.clone());
                     info!(
                         "[PRE-PREPARE] view:{}, sequence:{} pre-prepared",
                         m_view, m_seq
                     );
-                    self.event(Event::new_broadcast(
-                        self.member.local_id() as u64,
-                        m.clone(),
-                    ))
-                    .await;
+                    self.next_seq = self.next_seq.max(m_seq);
+                    self.watch_window();
+                    self.prepare(index, &m).await;
+                } else if self.queue[index].digest != m.digest {
+                    self.reject(RejectReason::DigestMismatch, &m);
                 }
             }
             Some(Payload::Prepare(ref prepare)) => {
@@ -136,29 +311,37 @@ impl<T: Membership> Pool<T> {
                     "[PREPARE] received prepare message from node{}. view:{}, sequence:{}",
                     m.id, m_view, m_seq
                 );
-                if let hash_map::Entry::Vacant(e) = self.queue[index].prepare.entry(m.id as usize) {
-                    e.insert(prepare.clone());
-                }
-                if self.is_prepared(index) {
-                    info!("[PREPARE] view:{}, sequence:{} prepared", m_view, m_seq);
-                    self.event(Event::new_broadcast(
-                        self.member.local_id() as u64,
-                        m.clone(),
-                    ))
-                    .await;
+                let voted = self.queue[index]
+                    .prepare
+                    .get(&m.digest)
+                    .is_some_and(|votes| votes.contains_key(&(m.id as usize)));
+                if !voted {
+                    // prepares prove the prepared certificate in a view change, after a restart too
+                    if !self.persist(&m) {
+                        return;
+                    }
+                    self.queue[index]
+                        .prepare
+                        .entry(m.digest.clone())
+                        .or_default()
+                        .insert(m.id as usize, prepare.clone());
                 }
+                self.commit(index, m.view, m.seq).await;
             }
             Some(Payload::Commit(ref commit)) => {
                 debug!(
                     "[COMMIT] received commit message from node{}. view:{}, sequence:{}",
                     m.id, m_view, m_seq
                 );
-                if let hash_map::Entry::Vacant(e) = self.queue[index].commit.entry(m.id as usize) {
+                if let hash_map::Entry::Vacant(e) = self.queue[index]
+                    .commit
+                    .entry(m.digest.clone())
+                    .or_default()
+                    .entry(m.id as usize)
+                {
                     e.insert(commit.clone());
                 }
-                if self.is_commited(index) {
-                    self.event(Event::new_commit(m.clone())).await;
-                }
+                self.commit_local(index, m.view, m.seq).await;
             }
             _ => {
                 error!("no such message type");
@@ -166,6 +349,1098 @@ impl<T: Membership> Pool<T> {
         }
     }
 
+    /// backup accepted the pre-prepare `m`, vote for it and tell the others
+    async fn prepare(&mut self, index: usize, m: &Message) {
+        let local = self.member.local_id();
+        let mut event = Event::new_broadcast(local as u64, m.clone());
+        self.sign(&mut event.msg);
+        if !self.persist(&event.msg) {
+            return;
+        }
+        if let Some(Payload::Prepare(ref prepare)) = event.msg.payload {
+            self.queue[index]
+                .prepare
+                .entry(m.digest.clone())
+                .or_default()
+                .insert(local, prepare.clone());
+        }
+        self.event(event).await;
+        self.commit(index, m.view, m.seq).await;
+    }
+
+    /// once prepared, vote commit exactly once
+    async fn commit(&mut self, index: usize, view: u64, seq: u64) {
+        let local = self.member.local_id();
+        let digest = self.queue[index].digest.clone();
+        let voted = self.queue[index]
+            .commit
+            .get(&digest)
+            .is_some_and(|votes| votes.contains_key(&local));
+        if voted || !self.is_prepared(index) {
+            return;
+        }
+        info!("[PREPARE] view:{}, sequence:{} prepared", view, seq);
+        let mut m = Message {
+            view,
+            seq,
+            id: local as u64,
+            digest: digest.clone(),
+            payload: Some(Payload::Commit(Commit::default())),
+        };
+        // own votes are kept signed, they are served as proof to lagging replicas
+        self.sign(&mut m);
+        if !self.persist(&m) {
+            return;
+        }
+        if let Some(Payload::Commit(ref commit)) = m.payload {
+            self.queue[index]
+                .commit
+                .entry(digest)
+                .or_default()
+                .insert(local, commit.clone());
+        }
+        self.event(Event::new_broadcast_message(m)).await;
+        self.commit_local(index, view, seq).await;
+    }
+
+    async fn commit_local(&mut self, index: usize, view: u64, seq: u64) {
+        if self.queue[index].commited_local || !self.is_commited(index) {
+            return;
+        }
+        self.queue[index].commited_local = true;
+        let digest = self.queue[index].digest.clone();
+        self.view_change_attempts = 0;
+        if let Some((primary, pre_prepare)) = self.queue[index].pre_prepare.iter().next() {
+            for request in pre_prepare.requests.iter() {
+                if self.requests.remove(&request_digest(request)).is_some() {
+                    debug!("[COMMIT] sequence:{} stop request timer", seq);
+                }
+            }
+            let commits = self.queue[index]
+                .commit
+                .get(&digest)
+                .map(|votes| {
+                    votes
+                        .iter()
+                        .map(|(id, commit)| Message {
+                            view,
+                            seq,
+                            id: *id as u64,
+                            digest: digest.clone(),
+                            payload: Some(Payload::Commit(commit.clone())),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            let m = Message {
+                view,
+                seq,
+                id: *primary as u64,
+                digest,
+                payload: Some(Payload::PrePrepare(pre_prepare.clone())),
+            };
+            self.store
+                .commit(CommittedEntry {
+                    pre_prepare: Some(m.clone()),
+                    commits,
+                })
+                .await;
+            self.event(Event::new_commit(m)).await;
+        }
+    }
+
+    async fn on_request(&mut self, m: Message, request: Request) {
+        if m.seq != 0 {
+            warn!(
+                "[REQUEST] client chose sequence:{}, only the primary assigns sequences",
+                m.seq
+            );
+            return;
+        }
+        if self.view_changing {
+            self.drop_message(DropReason::ViewChanging, &m);
+            return;
+        }
+        debug!("[REQUEST] received request. view:{}", self.view);
+
+        if !self.member.is_leader() {
+            if self.window_full() {
+                debug!("[REQUEST] sequence window is full, the primary answers busy");
+                self.drop_message(DropReason::WindowFull, &m);
+                self.replies.busy(&request_digest(&request)).await;
+                return;
+            }
+            debug!("not leader, start request timer");
+            let deadline = Instant::now() + self.request_timeout;
+            self.requests
+                .entry(request_digest(&request))
+                .or_insert(deadline);
+            return;
+        }
+
+        if self.batch.contains(&request) {
+            debug!("[REQUEST] request already batched");
+            return;
+        }
+        // a request that would take the batch past its byte limit closes the batch first
+        if !self.batch_fits(&request) {
+            self.order_batch().await;
+        }
+        // the batch is still held because the window is full, tell the client to back off
+        if self.batch_full() || !self.batch_fits(&request) {
+            warn!(
+                "[REQUEST] sequence window ({}, {}) is full, client{} busy",
+                self.stable_checkpoint,
+                self.stable_checkpoint + self.capacity,
+                request.client
+            );
+            self.drop_message(DropReason::WindowFull, &m);
+            self.replies.busy(&request_digest(&request)).await;
+            return;
+        }
+        if self.batch.is_empty() {
+            self.batch_deadline = Some(Instant::now() + self.batch_delay);
+        }
+        self.batch_bytes += request.payload.len();
+        self.batch.push(request);
+        if self.batch_full() {
+            self.order_batch().await;
+        }
+    }
+
+    /// every sequence of the window is assigned, nothing more can be ordered until it slides
+    fn window_full(&self) -> bool {
+        self.next_seq + 1 >= self.stable_checkpoint + self.capacity
+    }
+
+    /// the primary answers busy while its window is full, so backups stop timing the requests
+    /// it did not order and time the window instead
+    fn watch_window(&mut self) {
+        if self.member.is_leader() || !self.window_full() || self.window_full_since.is_some() {
+            return;
+        }
+        self.window_full_since = Some(Instant::now());
+        let ordered: HashSet<String> = self
+            .queue
+            .iter()
+            .flat_map(|slot| slot.pre_prepare.values())
+            .flat_map(|pre_prepare| pre_prepare.requests.iter().map(request_digest))
+            .collect();
+        let before = self.requests.len();
+        self.requests.retain(|digest, _| ordered.contains(digest));
+        debug!(
+            "[REQUEST] sequence window is full, stop {} request timers",
+            before - self.requests.len()
+        );
+    }
+
+    fn batch_full(&self) -> bool {
+        self.batch.len() >= self.max_batch_size || self.batch_bytes >= self.max_batch_bytes
+    }
+
+    /// a request larger than the byte limit is only ever batched alone
+    fn batch_fits(&self, request: &Request) -> bool {
+        self.batch.is_empty() || self.batch_bytes + request.payload.len() <= self.max_batch_bytes
+    }
+
+    /// the primary orders the pending requests as one batch under the next sequence
+    async fn order_batch(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        // requests are not bound to a view, the primary orders them in its own view
+        let seq = self.next_seq + 1;
+        if self.view_seq_check(self.view, seq).is_err() {
+            warn!(
+                "[REQUEST] sequence window ({}, {}) is full, hold {} requests",
+                self.stable_checkpoint,
+                self.stable_checkpoint + self.capacity,
+                self.batch.len()
+            );
+            return;
+        }
+        self.next_seq = seq;
+        let requests = std::mem::take(&mut self.batch);
+        self.batch_bytes = 0;
+        self.batch_deadline = None;
+        info!(
+            "[REQUEST]is leader, broadcast pre-prepare message. view:{}, sequence:{}, requests:{}",
+            self.view,
+            seq,
+            requests.len()
+        );
+
+        let local = self.member.local_id();
+        let digest = batch_digest(&requests);
+        let pre_prepare = PrePrepare {
+            signature: vec![],
+            requests,
+            authenticator: BTreeMap::new(),
+        };
+        let mut m = Message {
+            view: self.view as u64,
+            seq: seq as u64,
+            id: local as u64,
+            digest: digest.clone(),
+            payload: Some(Payload::PrePrepare(pre_prepare)),
+        };
+        self.sign(&mut m);
+        if !self.persist(&m) {
+            return;
+        }
+        let index = self.index_in_queue(seq);
+        let slot = &mut self.queue[index];
+        slot.digest = digest;
+        if let Some(Payload::PrePrepare(ref pre_prepare)) = m.payload {
+            let _ = slot.pre_prepare.insert(local, pre_prepare.clone());
+        }
+        self.event(Event::new_broadcast_message(m)).await;
+    }
+
+    async fn start_view_change(&mut self, new_view: usize) {
+        if new_view <= self.view || (self.view_changing && new_view <= self.pending_view) {
+            return;
+        }
+        warn!(
+            "[VIEW-CHANGE] start view change. view:{} -> new view:{}",
+            self.view, new_view
+        );
+        self.view_changing = true;
+        self.pending_view = new_view;
+        // wait exponentially longer for every consecutive view change
+        let backoff = self.view_change_timeout * 2u32.saturating_pow(self.view_change_attempts);
+        self.view_change_deadline = Some(Instant::now() + backoff);
+        self.view_change_attempts = self.view_change_attempts.saturating_add(1);
+
+        let mut m = Message {
+            view: new_view as u64,
+            seq: self.stable_checkpoint as u64,
+            id: self.member.local_id() as u64,
+            digest: String::new(),
+            payload: Some(Payload::ViewChange(ViewChange {
+                new_view: new_view as u64,
+                stable_checkpoint: self.stable_checkpoint as u64,
+                prepared: self.prepared_certs(),
+                signature: vec![],
+                checkpoints: self.stable_proof.clone(),
+            })),
+        };
+        self.sign(&mut m);
+        if !self.persist(&m) {
+            return;
+        }
+        self.insert_view_change(self.member.local_id(), new_view, m.clone());
+        self.event(Event::new_broadcast_message(m)).await;
+
+        self.try_new_view(new_view).await;
+    }
+
+    /// the view change logged before a restart is announced again and timed anew
+    async fn resume_view_change(&mut self) {
+        warn!(
+            "[VIEW-CHANGE] resume view change. view:{} -> new view:{}",
+            self.view, self.pending_view
+        );
+        self.view_change_deadline = Some(Instant::now() + self.view_change_timeout);
+        let local = self.member.local_id();
+        if let Some(m) = self
+            .view_changes
+            .get(&self.pending_view)
+            .and_then(|v| v.get(&local))
+        {
+            self.event(Event::new_broadcast_message(m.clone())).await;
+        }
+    }
+
+    async fn on_view_change(&mut self, m: Message, view_change: ViewChange) {
+        let new_view = view_change.new_view as usize;
+        debug!(
+            "[VIEW-CHANGE] received view-change message from node{}. new view:{}",
+            m.id, new_view
+        );
+        if new_view <= self.view || m.view != view_change.new_view {
+            warn!("view-change new view:{} <= view:{}", new_view, self.view);
+            self.reject(RejectReason::ViewMismatch, &m);
+            return;
+        }
+        if !self.verify_all(&view_change.checkpoints)
+            || !checkpoint::view_change_proven(&view_change, self.commit_quorum())
+        {
+            warn!(
+                "view-change from node{} claims stable checkpoint:{} without proof",
+                m.id, view_change.stable_checkpoint
+            );
+            self.reject(RejectReason::InvalidViewChange, &m);
+            return;
+        }
+        if !self.certs_proven(&view_change) {
+            warn!(
+                "view-change from node{} carries an unproven prepared certificate",
+                m.id
+            );
+            self.reject(RejectReason::InvalidViewChange, &m);
+            return;
+        }
+
+        let from = m.id as usize;
+        let local = self.member.local_id();
+        if !self.insert_view_change(from, new_view, m) {
+            debug!(
+                "[VIEW-CHANGE] node{} already asked for a view above:{}",
+                from, new_view
+            );
+            return;
+        }
+
+        match self.primary(new_view) {
+            Some(primary) if primary == local => {
+                self.try_new_view(new_view).await;
+            }
+            Some(_) => {
+                let mut ack = Message {
+                    view: new_view as u64,
+                    seq: 0,
+                    id: local as u64,
+                    digest: String::new(),
+                    payload: Some(Payload::ViewChangeAck(ViewChangeAck {
+                        new_view: new_view as u64,
+                        node: from as u64,
+                        signature: vec![],
+                    })),
+                };
+                self.sign(&mut ack);
+                self.event(Event::new_broadcast_message(ack)).await;
+            }
+            None => {}
+        }
+
+        // f+1 nodes want a higher view, join the smallest of them
+        let mut higher: Vec<(usize, usize)> = self
+            .view_changes
+            .iter()
+            .filter(|(v, _)| **v > self.view)
+            .flat_map(|(v, senders)| senders.keys().map(move |id| (*id, *v)))
+            .filter(|(id, _)| *id != local)
+            .collect();
+        higher.sort_unstable_by_key(|(_, v)| *v);
+        let nodes: HashSet<usize> = higher.iter().map(|(id, _)| *id).collect();
+        if nodes.len() > quorum::max_faulty(self.member.members().len()) {
+            if let Some((_, v)) = higher.first() {
+                self.start_view_change(*v).await;
+            }
+        }
+    }
+
+    /// keep only the latest view-change of each sender, a faulty one cannot grow the pool
+    /// by asking for ever higher views. false if the sender already asked for a higher view
+    fn insert_view_change(&mut self, from: usize, new_view: usize, m: Message) -> bool {
+        let newer = self
+            .view_changes
+            .iter()
+            .any(|(v, senders)| *v > new_view && senders.contains_key(&from));
+        if newer {
+            return false;
+        }
+        for (v, senders) in self.view_changes.iter_mut() {
+            if *v < new_view {
+                senders.remove(&from);
+            }
+        }
+        self.view_changes.retain(|_, senders| !senders.is_empty());
+        self.view_changes
+            .entry(new_view)
+            .or_default()
+            .entry(from)
+            .or_insert(m);
+        true
+    }
+
+    async fn on_view_change_ack(&mut self, from: usize, ack: ViewChangeAck) {
+        let new_view = ack.new_view as usize;
+        if new_view <= self.view || self.primary(new_view) != Some(self.member.local_id()) {
+            return;
+        }
+        debug!(
+            "[VIEW-CHANGE-ACK] received ack from node{} for node{}. new view:{}",
+            from, ack.node, new_view
+        );
+        self.view_change_acks
+            .entry(new_view)
+            .or_default()
+            .entry(ack.node as usize)
+            .or_default()
+            .insert(from);
+
+        self.try_new_view(new_view).await;
+    }
+
+    async fn try_new_view(&mut self, new_view: usize) {
+        let local = self.member.local_id();
+        if new_view <= self.view || self.primary(new_view) != Some(local) {
+            return;
+        }
+        let Some(view_changes) = self.view_changes.get(&new_view) else {
+            return;
+        };
+        // a view-change is accepted once 2f-1 other nodes acknowledged it
+        let acks = self.view_change_acks.get(&new_view);
+        let needed = quorum::prepare_quorum(self.member.members().len()).saturating_sub(1);
+        let accepted: Vec<Message> = view_changes
+            .iter()
+            .filter(|(id, _)| {
+                **id == local
+                    || acks
+                        .and_then(|a| a.get(id))
+                        .map(|a| a.iter().filter(|acker| **acker != **id).count() >= needed)
+                        .unwrap_or(false)
+            })
+            .map(|(_, m)| m.clone())
+            .collect();
+        if accepted.len() < self.commit_quorum() || !accepted.iter().any(|m| m.id as usize == local)
+        {
+            return;
+        }
+
+        let mut pre_prepares = view_change::pre_prepares(new_view as u64, local as u64, &accepted);
+        // signed one by one, they prove prepared certificates in later view changes
+        for m in pre_prepares.iter_mut() {
+            self.sign(m);
+        }
+        info!(
+            "[NEW-VIEW] is new primary, broadcast new-view message. new view:{}, re-issued pre-prepare:{}",
+            new_view,
+            pre_prepares.len()
+        );
+        let mut m = Message {
+            view: new_view as u64,
+            seq: 0,
+            id: local as u64,
+            digest: String::new(),
+            payload: Some(Payload::NewView(NewView {
+                new_view: new_view as u64,
+                view_changes: accepted,
+                pre_prepares: pre_prepares.clone(),
+                signature: vec![],
+            })),
+        };
+        self.sign(&mut m);
+        // the new view is logged before it is announced
+        if !self.enter_view(new_view, pre_prepares).await {
+            return;
+        }
+        self.event(Event::new_broadcast_message(m)).await;
+    }
+
+    async fn on_new_view(&mut self, from: usize, new_view: NewView) -> Result<(), RejectReason> {
+        let view = new_view.new_view as usize;
+        debug!(
+            "[NEW-VIEW] received new-view message from node{}. new view:{}",
+            from, view
+        );
+        if view <= self.view {
+            warn!("new-view view:{} <= view:{}", view, self.view);
+            return Err(RejectReason::ViewMismatch);
+        }
+        if self.primary(view) != Some(from) {
+            warn!(
+                "new-view from node{} which is not primary of view:{}",
+                from, view
+            );
+            return Err(RejectReason::InvalidNewView);
+        }
+
+        let mut senders = HashSet::new();
+        for m in new_view.view_changes.iter() {
+            match m.payload {
+                Some(Payload::ViewChange(ref vc))
+                    if vc.new_view == new_view.new_view
+                        && m.view == new_view.new_view
+                        && self.verify(m)
+                        && self.verify_all(&vc.checkpoints)
+                        && checkpoint::view_change_proven(vc, self.commit_quorum())
+                        && self.certs_proven(vc) => {}
+                _ => {
+                    warn!("new-view carries an invalid view-change message");
+                    return Err(RejectReason::InvalidNewView);
+                }
+            }
+            senders.insert(m.id);
+        }
+        if senders.len() < self.commit_quorum() {
+            warn!(
+                "new-view carries {} view-change messages, not enough",
+                senders.len()
+            );
+            return Err(RejectReason::InvalidNewView);
+        }
+
+        let expected =
+            view_change::pre_prepares(new_view.new_view, from as u64, &new_view.view_changes);
+        let unsigned: Vec<Message> = new_view.pre_prepares.iter().map(crypto::unsigned).collect();
+        if expected != unsigned || !self.verify_all(&new_view.pre_prepares) {
+            warn!("new-view pre-prepare messages do not match its view-change messages");
+            return Err(RejectReason::InvalidNewView);
+        }
+
+        let min_s = view_change::min_seq(&new_view.view_changes) as usize;
+        if min_s > self.stable_checkpoint {
+            let proof = new_view.view_changes.iter().find_map(|m| match m.payload {
+                Some(Payload::ViewChange(ref vc)) if vc.stable_checkpoint as usize == min_s => {
+                    Some(vc.checkpoints.clone())
+                }
+                _ => None,
+            });
+            self.advance_stable_checkpoint(min_s, proof.unwrap_or_default())
+                .await;
+        }
+
+        info!("[NEW-VIEW] view:{} accepted", view);
+        self.enter_view(view, new_view.pre_prepares).await;
+        Ok(())
+    }
+
+    /// move to `view` and take the pre-prepares re-issued for it. false if anything failed
+    /// to be logged, the view must not be announced then
+    async fn enter_view(&mut self, view: usize, pre_prepares: Vec<Message>) -> bool {
+        if !self.log(WalRecord {
+            record: Some(Record::View(view as u64)),
+        }) {
+            return false;
+        }
+        self.view = view;
+        self.metrics.set_view(view as u64);
+        self.view_changing = false;
+        self.pending_view = view;
+        self.view_change_deadline = None;
+        self.window_full_since = None;
+        // give the new primary a full timeout for the requests still waiting
+        let deadline = Instant::now() + self.request_timeout;
+        for d in self.requests.values_mut() {
+            *d = deadline;
+        }
+        self.view_changes.retain(|v, _| *v > view);
+        self.view_change_acks.retain(|v, _| *v > view);
+        for slot in self.queue.iter_mut() {
+            slot.clear();
+        }
+
+        self.member.enter_view(view);
+        if !self.member.is_leader() {
+            // clients retransmit to the new primary, backups still time the requests
+            self.batch.clear();
+            self.batch_bytes = 0;
+            self.batch_deadline = None;
+        }
+
+        // sequence numbers count on from the highest one re-issued by the new primary, or
+        // from the stable checkpoint. those only pre-prepared in the old view are reused
+        let max_s = pre_prepares.iter().map(|m| m.seq as usize).max();
+        self.next_seq = max_s.unwrap_or(0).max(self.stable_checkpoint);
+
+        let primary = self.primary(view);
+        let mut logged = true;
+        for m in pre_prepares {
+            let m_seq = m.seq as usize;
+            if self.view_seq_check(view, m_seq).is_err() {
+                continue;
+            }
+            if primary != Some(m.id as usize) {
+                self.reject(RejectReason::InvalidPrePrepare, &m);
+                continue;
+            }
+            let index = self.index_in_queue(m_seq);
+            if let Some(Payload::PrePrepare(ref pre_prepare)) = m.payload {
+                if !self.persist(&m) {
+                    logged = false;
+                    continue;
+                }
+                let slot = &mut self.queue[index];
+                slot.digest = m.digest.clone();
+                let _ = slot.pre_prepare.insert(m.id as usize, pre_prepare.clone());
+            }
+            if !self.member.is_leader() {
+                self.prepare(index, &m).await;
+            }
+        }
+        logged
+    }
+
+    async fn on_checkpoint(&mut self, m: Message, cp: Checkpoint) {
+        let seq = cp.seq as usize;
+        debug!(
+            "[CHECKPOINT] received checkpoint message from node{}. sequence:{}",
+            m.id, seq
+        );
+        if seq <= self.stable_checkpoint {
+            debug!(
+                "checkpoint sequence:{} at or below stable checkpoint:{}",
+                seq, self.stable_checkpoint
+            );
+            self.drop_message(DropReason::StaleCheckpoint, &m);
+            return;
+        }
+        if seq >= self.stable_checkpoint + self.capacity {
+            debug!(
+                "checkpoint sequence:{} at or above high watermark:{}",
+                seq,
+                self.stable_checkpoint + self.capacity
+            );
+            self.drop_message(DropReason::CheckpointAhead, &m);
+            self.on_checkpoint_ahead(m, &cp).await;
+            return;
+        }
+        if !self.persist(&m) {
+            return;
+        }
+        // own checkpoints come from the event handler and are announced once logged
+        if m.id as usize == self.member.local_id() {
+            self.event(Event::new_broadcast_message(m.clone())).await;
+        }
+        self.insert_checkpoint(m, &cp).await;
+    }
+
+    async fn insert_checkpoint(&mut self, m: Message, cp: &Checkpoint) {
+        let seq = cp.seq as usize;
+        self.checkpoints
+            .entry(seq)
+            .or_default()
+            .insert(m.id as usize, m);
+
+        let proof: Vec<Message> = self.checkpoints[&seq]
+            .values()
+            .filter(
+                |m| matches!(m.payload, Some(Payload::Checkpoint(ref c)) if c.digest == cp.digest),
+            )
+            .cloned()
+            .collect();
+        if checkpoint::is_proven(cp.seq, &cp.digest, &proof, self.commit_quorum()) {
+            self.advance_stable_checkpoint(seq, proof).await;
+        }
+    }
+
+    /// a quorum of checkpoints past the high watermark proves that this replica fell
+    /// more than a window behind, it jumps to that checkpoint and fetches the state
+    async fn on_checkpoint_ahead(&mut self, m: Message, cp: &Checkpoint) {
+        let id = m.id as usize;
+        if self
+            .checkpoints_ahead
+            .get(&id)
+            .is_some_and(|latest| checkpoint_seq(latest) >= cp.seq)
+        {
+            return;
+        }
+        self.checkpoints_ahead.insert(id, m);
+
+        let proof: Vec<Message> = self
+            .checkpoints_ahead
+            .values()
+            .filter(
+                |m| matches!(m.payload, Some(Payload::Checkpoint(ref c)) if c.seq == cp.seq && c.digest == cp.digest),
+            )
+            .cloned()
+            .collect();
+        if checkpoint::is_proven(cp.seq, &cp.digest, &proof, self.commit_quorum()) {
+            warn!(
+                "[CHECKPOINT] sequence:{} proven past high watermark:{}, fetch state",
+                cp.seq,
+                self.stable_checkpoint + self.capacity
+            );
+            self.advance_stable_checkpoint(cp.seq as usize, proof).await;
+        }
+    }
+
+    async fn advance_stable_checkpoint(&mut self, seq: usize, proof: Vec<Message>) {
+        // free the slots of (stable_checkpoint, seq] and move the ring buffer forward
+        let moved = seq - self.stable_checkpoint;
+        for s in self.stable_checkpoint + 1..=self.stable_checkpoint + moved.min(self.capacity) {
+            let index = self.index_in_queue(s);
+            self.queue[index].clear();
+        }
+        self.start = (self.start + moved) % self.capacity;
+        self.stable_checkpoint = seq;
+        self.next_seq = self.next_seq.max(seq);
+        self.stable_proof = proof.clone();
+        self.metrics.set_stable_checkpoint(seq as u64);
+        self.window_full_since = None;
+        self.watch_window();
+        self.store.stable(seq as u64, proof).await;
+        self.checkpoints.retain(|s, _| *s > seq);
+        let high = seq + self.capacity;
+        self.checkpoints_ahead
+            .retain(|_, m| checkpoint_seq(m) as usize >= high);
+        info!(
+            "[CHECKPOINT] sequence:{} stable, watermarks ({}, {})",
+            seq,
+            self.stable_checkpoint,
+            self.stable_checkpoint + self.capacity
+        );
+        self.compact_wal();
+        // the executor catches up by state transfer if it is behind the stable checkpoint
+        self.event(Event::new_stable(Message {
+            view: self.view as u64,
+            seq: seq as u64,
+            id: self.member.local_id() as u64,
+            digest: String::new(),
+            payload: None,
+        }))
+        .await;
+    }
+
+    /// log a message before it is acted upon. nothing may be sent for what failed to be logged
+    fn persist(&mut self, m: &Message) -> bool {
+        self.log(WalRecord {
+            record: Some(Record::Message(m.clone())),
+        })
+    }
+
+    fn log(&mut self, record: WalRecord) -> bool {
+        let Some(ref mut wal) = self.wal else {
+            return true;
+        };
+        match wal.append(&record) {
+            Ok(()) => true,
+            Err(err) => {
+                let total = self.metrics.drop_message(DropReason::NotPersisted);
+                error!(
+                    "[WAL] append err: {}, message dropped. total:{}",
+                    err, total
+                );
+                false
+            }
+        }
+    }
+
+    /// rewrite the log with only what is still needed after the stable checkpoint
+    fn compact_wal(&mut self) {
+        if self.wal.is_none() {
+            return;
+        }
+        let records = self.wal_records();
+        if let Some(ref mut wal) = self.wal {
+            if let Err(err) = wal.compact(&records) {
+                error!("[WAL] compact err: {}", err);
+            }
+        }
+    }
+
+    fn wal_records(&self) -> Vec<WalRecord> {
+        let local = self.member.local_id();
+        let mut messages: Vec<Message> = self.stable_proof.clone();
+        let mut seqs: Vec<&usize> = self.checkpoints.keys().collect();
+        seqs.sort_unstable();
+        for seq in seqs {
+            messages.extend(self.checkpoints[seq].values().cloned());
+        }
+        for seq in self.stable_checkpoint + 1..self.stable_checkpoint + self.capacity {
+            let slot = &self.queue[self.index_in_queue(seq)];
+            let message = |id: usize, payload: Payload| Message {
+                view: self.view as u64,
+                seq: seq as u64,
+                id: id as u64,
+                digest: slot.digest.clone(),
+                payload: Some(payload),
+            };
+            for (id, pre_prepare) in slot.pre_prepare.iter() {
+                messages.push(message(*id, Payload::PrePrepare(pre_prepare.clone())));
+            }
+            if let Some(votes) = slot.prepare.get(&slot.digest) {
+                for (id, prepare) in votes.iter() {
+                    messages.push(message(*id, Payload::Prepare(prepare.clone())));
+                }
+            }
+            if let Some(commit) = slot.commit.get(&slot.digest).and_then(|v| v.get(&local)) {
+                messages.push(message(local, Payload::Commit(commit.clone())));
+            }
+        }
+        if self.view_changing {
+            if let Some(m) = self
+                .view_changes
+                .get(&self.pending_view)
+                .and_then(|v| v.get(&local))
+            {
+                messages.push(m.clone());
+            }
+        }
+
+        let mut records = vec![WalRecord {
+            record: Some(Record::View(self.view as u64)),
+        }];
+        records.extend(messages.into_iter().map(|m| WalRecord {
+            record: Some(Record::Message(m)),
+        }));
+        records
+    }
+
+    /// apply a logged record, without sending anything
+    async fn replay(&mut self, record: WalRecord) {
+        let local = self.member.local_id();
+        match record.record {
+            Some(Record::View(view)) => {
+                let view = view as usize;
+                if view <= self.view {
+                    return;
+                }
+                self.view = view;
+                self.view_changing = false;
+                self.pending_view = view;
+                for slot in self.queue.iter_mut() {
+                    slot.clear();
+                }
+                self.member.enter_view(view);
+            }
+            Some(Record::Message(m)) => {
+                if let Some(Payload::Checkpoint(ref cp)) = m.payload {
+                    let seq = cp.seq as usize;
+                    if seq > self.stable_checkpoint && seq < self.stable_checkpoint + self.capacity
+                    {
+                        let cp = cp.clone();
+                        self.insert_checkpoint(m, &cp).await;
+                    }
+                    return;
+                }
+                if let Some(Payload::ViewChange(ref vc)) = m.payload {
+                    let new_view = vc.new_view as usize;
+                    if new_view > self.view {
+                        self.view_changing = true;
+                        self.pending_view = self.pending_view.max(new_view);
+                        self.insert_view_change(local, new_view, m);
+                    }
+                    return;
+                }
+                if self
+                    .view_seq_check(m.view as usize, m.seq as usize)
+                    .is_err()
+                {
+                    return;
+                }
+                let index = self.index_in_queue(m.seq as usize);
+                let slot = &mut self.queue[index];
+                match m.payload {
+                    Some(Payload::PrePrepare(pre_prepare)) => {
+                        slot.digest = m.digest;
+                        slot.pre_prepare.insert(m.id as usize, pre_prepare);
+                        self.next_seq = self.next_seq.max(m.seq as usize);
+                    }
+                    Some(Payload::Prepare(prepare)) => {
+                        slot.prepare
+                            .entry(m.digest)
+                            .or_default()
+                            .insert(m.id as usize, prepare);
+                    }
+                    Some(Payload::Commit(commit)) => {
+                        // the commit vote was only cast once prepared
+                        slot.prepared_local = true;
+                        slot.commit
+                            .entry(m.digest)
+                            .or_default()
+                            .insert(local, commit);
+                    }
+                    _ => {}
+                }
+            }
+            None => {}
+        }
+    }
+
+    async fn check_timers(&mut self) {
+        let now = Instant::now();
+        if self.view_changing {
+            if self.view_change_deadline.is_some_and(|d| d <= now) {
+                warn!(
+                    "[TIMER] view change to {} timed out, try next view",
+                    self.pending_view
+                );
+                self.start_view_change(self.pending_view + 1).await;
+            }
+            return;
+        }
+        if self.requests.values().any(|d| *d <= now) {
+            warn!(
+                "[TIMER] request timed out in view:{}, primary suspected",
+                self.view
+            );
+            self.start_view_change(self.view + 1).await;
+            return;
+        }
+        // a full window has to slide within a request timeout
+        if self
+            .window_full_since
+            .is_some_and(|since| since + self.request_timeout <= now)
+        {
+            warn!(
+                "[TIMER] sequence window ({}, {}) full for too long in view:{}, primary suspected",
+                self.stable_checkpoint,
+                self.stable_checkpoint + self.capacity,
+                self.view
+            );
+            self.start_view_change(self.view + 1).await;
+            return;
+        }
+        if self.batch_deadline.is_some_and(|d| d <= now) {
+            self.order_batch().await;
+        }
+    }
+
+    fn prepared_certs(&self) -> Vec<PreparedCert> {
+        let mut certs = Vec::new();
+        for seq in self.stable_checkpoint + 1..self.stable_checkpoint + self.capacity {
+            let index = self.index_in_queue(seq);
+            if !self.is_prepared(index) {
+                continue;
+            }
+            let slot = &self.queue[index];
+            let message = |id: usize, payload: Payload| Message {
+                view: self.view as u64,
+                seq: seq as u64,
+                id: id as u64,
+                digest: slot.digest.clone(),
+                payload: Some(payload),
+            };
+            if let Some((primary, pre_prepare)) = slot.pre_prepare.iter().next() {
+                let prepares = slot
+                    .prepare
+                    .get(&slot.digest)
+                    .map(|votes| {
+                        votes
+                            .iter()
+                            .filter(|(id, _)| *id != primary)
+                            .map(|(id, prepare)| message(*id, Payload::Prepare(prepare.clone())))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                certs.push(PreparedCert {
+                    view: self.view as u64,
+                    seq: seq as u64,
+                    digest: slot.digest.clone(),
+                    requests: pre_prepare.requests.clone(),
+                    pre_prepare: Some(message(*primary, Payload::PrePrepare(pre_prepare.clone()))),
+                    prepares,
+                });
+            }
+        }
+        certs
+    }
+
+    /// every prepared certificate of a view-change message has to lie within the watermarks
+    /// of its stable checkpoint, and needs the pre-prepare of the primary of its view and
+    /// 2f prepares from the backups, all for the digest of its requests
+    fn certs_proven(&self, view_change: &ViewChange) -> bool {
+        let n = self.member.members().len();
+        let low = view_change.stable_checkpoint;
+        let high = low.saturating_add(self.capacity as u64);
+        view_change.prepared.iter().all(|cert| {
+            if cert.seq <= low || cert.seq >= high {
+                return false;
+            }
+            if cert.view >= view_change.new_view || cert.digest != batch_digest(&cert.requests) {
+                return false;
+            }
+            let primary = self.primary(cert.view as usize);
+            let agrees = |m: &Message| {
+                m.view == cert.view
+                    && m.seq == cert.seq
+                    && m.digest == cert.digest
+                    && self.verify(m)
+            };
+            let pre_prepared = cert.pre_prepare.as_ref().is_some_and(|m| {
+                matches!(m.payload, Some(Payload::PrePrepare(_)))
+                    && primary == Some(m.id as usize)
+                    && agrees(m)
+            });
+            let backups: HashSet<u64> = cert
+                .prepares
+                .iter()
+                .filter(|m| {
+                    matches!(m.payload, Some(Payload::Prepare(_)))
+                        && primary != Some(m.id as usize)
+                        && agrees(m)
+                })
+                .map(|m| m.id)
+                .collect();
+            pre_prepared && backups.len() >= quorum::prepare_quorum(n)
+        })
+    }
+
+    fn primary(&self, view: usize) -> Option<usize> {
+        view_change::primary(view, &self.member.members())
+    }
+
+    fn sign(&self, m: &mut Message) {
+        if self.authenticators && crypto::has_authenticator(m) {
+            let local = self.member.local_id();
+            let keys = self
+                .member
+                .members()
+                .into_keys()
+                .filter(|id| *id != local)
+                .filter_map(|id| self.member.mac_key(id).map(|key| (id, key)))
+                .collect();
+            crypto::authenticate(m, &keys);
+        } else if let Some(ref key) = self.signing_key {
+            crypto::sign(m, key);
+        }
+    }
+
+    /// messages from replicas must come from a member and be signed by the key bound to
+    /// its node id, or carry a mac made with the key shared with it in the normal case.
+    /// signatures are not checked when signing is not configured
+    fn authenticate(&self, m: &Message) -> Result<(), RejectReason> {
+        if let Some(Payload::Request(_)) = m.payload {
+            return Ok(());
+        }
+        if !self.member.members().contains_key(&(m.id as usize)) {
+            return Err(RejectReason::UnknownSender);
+        }
+        if self.authenticators && crypto::has_authenticator(m) {
+            // there is no mac for the replica itself in its own messages, they are made again
+            if m.id as usize == self.member.local_id() {
+                let mut own = m.clone();
+                self.sign(&mut own);
+                if own != *m {
+                    return Err(RejectReason::InvalidAuthenticator);
+                }
+                return Ok(());
+            }
+            return match self.member.mac_key(m.id as usize) {
+                Some(key) if crypto::verify_mac(m, self.member.local_id(), &key) => Ok(()),
+                _ => Err(RejectReason::InvalidAuthenticator),
+            };
+        }
+        if self.signing_key.is_none() {
+            return Ok(());
+        }
+        match self.member.public_key(m.id as usize) {
+            Some(key) if crypto::verify(m, &key) => Ok(()),
+            _ => Err(RejectReason::InvalidSignature),
+        }
+    }
+
+    fn verify(&self, m: &Message) -> bool {
+        self.authenticate(m).is_ok()
+    }
+
+    fn verify_all(&self, messages: &[Message]) -> bool {
+        messages.iter().all(|m| self.verify(m))
+    }
+
+    fn reject(&self, reason: RejectReason, m: &Message) {
+        let total = self.metrics.reject(reason);
+        warn!(
+            "[REJECT] {} message from node{}. view:{}, sequence:{}, total:{}",
+            reason, m.id, m.view, m.seq, total
+        );
+    }
+
+    /// a well-formed message this replica deliberately does not process
+    fn drop_message(&self, reason: DropReason, m: &Message) {
+        let total = self.metrics.drop_message(reason);
+        debug!(
+            "[DROP] {} message from node{}. view:{}, sequence:{}, total:{}",
+            reason, m.id, m.view, m.seq, total
+        );
+    }
+
+    /// hand an event to the event handler, messages to broadcast are signed already
     async fn event(&self, event: Event) {
         if let Err(err) = self.event_sender.send(event).await {
             error!("event sender error:{}", err);
@@ -177,35 +1452,52 @@ impl<T: Membership> Pool<T> {
     }
 
     fn is_prepared(&self, index: usize) -> bool {
-        self.is_pre_prepared(index) && self.counts_prepare(index) >= self.bft_node_num()
+        self.is_pre_prepared(index)
+            && (self.queue[index].prepared_local
+                || self.counts_prepare(index)
+                    >= quorum::prepare_quorum(self.member.members().len()))
     }
 
     fn is_commited(&self, index: usize) -> bool {
-        self.is_prepared(index) && self.counts_commit(index) >= self.bft_node_num()
+        self.is_prepared(index) && self.counts_commit(index) >= self.commit_quorum()
     }
 
+    /// prepares matching the pre-prepare, the primary itself does not prepare
     fn counts_prepare(&self, index: usize) -> usize {
-        self.queue[index].prepare.len()
+        let slot = &self.queue[index];
+        slot.prepare.get(&slot.digest).map_or(0, |votes| {
+            votes
+                .keys()
+                .filter(|id| !slot.pre_prepare.contains_key(id))
+                .count()
+        })
     }
     fn counts_commit(&self, index: usize) -> usize {
-        self.queue[index].commit.len()
+        let slot = &self.queue[index];
+        slot.commit.get(&slot.digest).map_or(0, |votes| votes.len())
     }
-    fn bft_node_num(&self) -> usize {
-        // 2f
-        (self.member.members().len() * 2) / 3
+    fn commit_quorum(&self) -> usize {
+        // 2f+1 when n = 3f+1
+        quorum::commit_quorum(self.member.members().len())
     }
     fn index_in_queue(&self, seq: usize) -> usize {
-        seq - self.stable_checkpoint + self.start
+        (seq - self.stable_checkpoint + self.start) % self.capacity
     }
-    fn view_seq_check(&self, view: usize, seq: usize) -> bool {
+    fn view_seq_check(&self, view: usize, seq: usize) -> Result<(), RejectReason> {
         if seq <= self.stable_checkpoint || seq >= self.stable_checkpoint + self.capacity {
-            warn!("seq <= stable_checkpoint || seq >= self.stable_checkpoint + self.capacity");
-            return false;
+            return Err(RejectReason::OutOfWindow);
         }
         if view != self.view {
-            warn!("view != self.view");
-            return false;
+            return Err(RejectReason::ViewMismatch);
         }
-        true
+        Ok(())
+    }
+}
+
+/// the sequence of the checkpoint carried by `m`
+fn checkpoint_seq(m: &Message) -> u64 {
+    match m.payload {
+        Some(Payload::Checkpoint(ref cp)) => cp.seq,
+        _ => 0,
     }
 }
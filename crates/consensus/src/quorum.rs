@@ -0,0 +1,32 @@
+use crate::error::ConsensusError;
+
+/// smallest cluster that tolerates a faulty node, n >= 3f+1
+pub const MIN_MEMBERS: usize = 4;
+
+/// f = ⌊(n-1)/3⌋, the number of faulty nodes a cluster of `n` tolerates
+pub fn max_faulty(n: usize) -> usize {
+    n.saturating_sub(1) / 3
+}
+
+/// matching prepares from backups needed besides the pre-prepare, 2f when n = 3f+1
+pub fn prepare_quorum(n: usize) -> usize {
+    commit_quorum(n) - 1
+}
+
+/// matching commits (and view-change, checkpoint messages) needed, ⌈(n+f+1)/2⌉.
+/// any two such quorums share an honest node, 2f+1 when n = 3f+1
+pub fn commit_quorum(n: usize) -> usize {
+    (n + max_faulty(n) + 2) / 2
+}
+
+/// matching replies a client needs, f+1
+pub fn reply_quorum(n: usize) -> usize {
+    max_faulty(n) + 1
+}
+
+pub fn validate(n: usize) -> Result<(), ConsensusError> {
+    if n < MIN_MEMBERS {
+        return Err(ConsensusError::ClusterTooSmall(n));
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use crate::message::NodeStatus;
+use crate::peers::Peers;
+use crate::quorum;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{task::JoinSet, time};
+use tracing::{debug, warn};
+
+const STATUS_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// ask every other member for its view and stable checkpoint, peers that do not answer are skipped
+pub async fn peer_status(
+    peers: &Arc<Peers>,
+    local: usize,
+    members: HashMap<usize, String>,
+) -> Vec<NodeStatus> {
+    let mut tasks = JoinSet::new();
+    for (id, addr) in members {
+        if id != local {
+            let peers = peers.clone();
+            tasks.spawn(async move {
+                let status = time::timeout(STATUS_TIMEOUT, peers.status(id, &addr)).await;
+                (status, addr)
+            });
+        }
+    }
+    let mut statuses = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((Ok(Ok(s)), addr)) => {
+                debug!(
+                    "status of addr {}: view:{}, stable checkpoint:{}",
+                    addr, s.view, s.stable_checkpoint
+                );
+                statuses.push(s);
+            }
+            Ok((Ok(Err(err)), addr)) => warn!("get status of addr {} err: {}", addr, err),
+            Ok((Err(_), addr)) => warn!("get status of addr {} timed out", addr),
+            Err(err) => warn!("get status task err: {}", err),
+        }
+    }
+    statuses
+}
+
+/// the highest view reached by f+1 replicas, at least one of them is correct
+pub fn current_view(statuses: &[NodeStatus], n: usize) -> Option<u64> {
+    reached(statuses.iter().map(|s| s.view).collect(), n)
+}
+
+/// the highest stable checkpoint reached by f+1 replicas
+pub fn stable_checkpoint(statuses: &[NodeStatus], n: usize) -> Option<u64> {
+    reached(statuses.iter().map(|s| s.stable_checkpoint).collect(), n)
+}
+
+fn reached(mut values: Vec<u64>, n: usize) -> Option<u64> {
+    values.sort_unstable_by(|a, b| b.cmp(a));
+    values.get(quorum::reply_quorum(n) - 1).copied()
+}
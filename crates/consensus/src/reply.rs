@@ -0,0 +1,70 @@
+use crate::message::Reply;
+use std::collections::HashMap;
+use tokio::sync::{oneshot, Mutex};
+
+/// clients waiting for their requests to be executed, keyed by request digest,
+/// and the last reply sent to every client
+#[derive(Default)]
+pub struct Replies {
+    waiters: Mutex<HashMap<String, Vec<oneshot::Sender<Reply>>>>,
+    last: Mutex<HashMap<u64, Reply>>,
+}
+
+impl Replies {
+    pub async fn last(&self, client: u64) -> Option<Reply> {
+        self.last.lock().await.get(&client).cloned()
+    }
+
+    /// the last reply of every client, kept in snapshots
+    pub async fn all(&self) -> Vec<Reply> {
+        self.last.lock().await.values().cloned().collect()
+    }
+
+    /// replace the last replies with the ones of a snapshot
+    pub async fn restore(&self, replies: Vec<Reply>) {
+        *self.last.lock().await = replies
+            .into_iter()
+            .map(|reply| (reply.client, reply))
+            .collect();
+    }
+
+    pub async fn record(&self, reply: Reply) {
+        let mut last = self.last.lock().await;
+        match last.get(&reply.client) {
+            Some(prev) if prev.timestamp >= reply.timestamp => {}
+            _ => {
+                last.insert(reply.client, reply);
+            }
+        }
+    }
+
+    pub async fn wait(&self, digest: String) -> oneshot::Receiver<Reply> {
+        let (tx, rx) = oneshot::channel();
+        self.waiters
+            .lock()
+            .await
+            .entry(digest)
+            .or_default()
+            .push(tx);
+        rx
+    }
+
+    /// the request was not accepted, its waiters are dropped without a reply
+    pub async fn busy(&self, digest: &str) {
+        self.waiters.lock().await.remove(digest);
+    }
+
+    pub async fn notify(&self, digest: &str, reply: Reply) {
+        let mut waiters = self.waiters.lock().await;
+        if let Some(senders) = waiters.remove(digest) {
+            for sender in senders {
+                let _ = sender.send(reply.clone());
+            }
+        }
+        // forget clients that gave up waiting
+        waiters.retain(|_, senders| {
+            senders.retain(|s| !s.is_closed());
+            !senders.is_empty()
+        });
+    }
+}
@@ -1,65 +1,450 @@
-use crate::members::Members;
+use crate::members::{Members, Membership};
+use crate::metrics::{DropReason, Metrics, RejectReason};
+use crate::peers::{ConnectionState, Peers, Transport, DEFAULT_QUEUE_CAPACITY};
+use crate::quorum;
+use crate::recovery;
+use crate::reply::Replies;
+use crate::snapshot;
+use crate::state_machine::StateMachine;
+use crate::tls::{self, TlsOptions};
+use crate::transfer::Store;
+use crate::wal::{SyncPolicy, Wal};
 use crate::{
+    digest::request_digest,
     error::ConsensusError,
     event::EventHandler,
     message::{
+        message::Payload,
         pbft_server::{Pbft, PbftServer},
-        Message, MessageResponse,
+        CommittedRange, CommittedRangeRequest, FetchCheckpointRequest, Message, MessageResponse,
+        MetricsReport, MetricsRequest, NodeStatus, NodeStatusRequest, PeerMetrics, Reply,
+        SnapshotChunk,
     },
     pool::RequestHandler,
 };
-use std::sync::Arc;
-use tokio::sync::{mpsc, mpsc::Sender};
-use tonic::{transport::Server as TransportServer, Response};
-use tracing::{debug, error, info};
+use ed25519_dalek::SigningKey;
+use std::{path::PathBuf, pin::Pin, sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc, mpsc::Sender},
+    time,
+};
+use tonic::{
+    codegen::tokio_stream::{self, wrappers::ReceiverStream, Stream},
+    transport::{Certificate, Server as TransportServer},
+    Response,
+};
+use tracing::{debug, error, info, warn};
+
+// responses buffered for a replica reading a message stream
+const STREAM_BUFFER: usize = 64;
+
+pub struct Options {
+    /// how long a backup waits for a request to commit before suspecting the primary
+    pub request_timeout: Duration,
+    /// how long to wait for a new view, doubled for every consecutive view change
+    pub view_change_timeout: Duration,
+    /// a checkpoint is taken every `checkpoint_interval` sequence numbers
+    pub checkpoint_interval: u64,
+    /// key for signing protocol messages, messages are neither signed nor verified without it
+    pub signing_key: Option<SigningKey>,
+    /// authenticate pre-prepare, prepare and commit with macs made with the pairwise keys
+    /// of the members, signatures are then left to view changes and checkpoints.
+    /// requires `signing_key`
+    pub authenticators: bool,
+    /// how long a client request waits for its execution
+    pub reply_timeout: Duration,
+    /// max requests in a batch ordered by the primary
+    pub batch_size: usize,
+    /// max payload bytes in a batch
+    pub batch_bytes: usize,
+    /// max time the first request of a batch waits before the batch is ordered
+    pub batch_delay: Duration,
+    /// distance between the low and high watermarks, at most this many sequences are in flight
+    pub window: usize,
+    /// directory of the write-ahead log and checkpoint snapshots,
+    /// a node restarts empty without it
+    pub data_dir: Option<PathBuf>,
+    pub wal_sync: SyncPolicy,
+    /// max broadcast messages queued for a peer, the oldest are dropped when it falls behind
+    pub send_queue: usize,
+    pub transport: Transport,
+    /// mutual tls between the replicas, plaintext without it
+    pub tls: Option<TlsOptions>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            request_timeout: Duration::from_millis(2000),
+            view_change_timeout: Duration::from_millis(4000),
+            checkpoint_interval: 5,
+            signing_key: None,
+            authenticators: false,
+            reply_timeout: Duration::from_millis(10000),
+            batch_size: 64,
+            batch_bytes: 1024 * 1024,
+            batch_delay: Duration::from_millis(10),
+            window: 20,
+            data_dir: None,
+            wal_sync: SyncPolicy::Always,
+            send_queue: DEFAULT_QUEUE_CAPACITY,
+            transport: Transport::Stream,
+            tls: None,
+        }
+    }
+}
 
+#[derive(Clone)]
 pub struct Server {
+    local: usize,
+    member: Arc<Members>,
+    metrics: Arc<Metrics>,
+    peers: Arc<Peers>,
     sender: Sender<Message>,
+    replies: Arc<Replies>,
+    store: Arc<Store>,
+    reply_timeout: Duration,
+    tls: bool,
 }
 
 impl Server {
-    async fn request(&self, msg: Message) -> Result<(), ConsensusError> {
-        if let Err(e) = self.sender.send(msg).await {
-            error!("send request to pool err: {}", e)
+    /// hand the message to the pool. client requests wait for their execution
+    async fn request(&self, msg: Message) -> Result<Option<Reply>, ConsensusError> {
+        let mut waiter = None;
+        if let Some(Payload::Request(ref request)) = msg.payload {
+            if msg.seq != 0 {
+                return Err(ConsensusError::SequenceNotAllowed());
+            }
+            if let Some(last) = self.replies.last(request.client).await {
+                if request.timestamp == last.timestamp {
+                    debug!(
+                        "[REQUEST] client{} timestamp:{} answered from cache",
+                        request.client, request.timestamp
+                    );
+                    return Ok(Some(last));
+                }
+                if request.timestamp < last.timestamp {
+                    return Err(ConsensusError::StaleRequest(last.timestamp));
+                }
+            }
+            waiter = Some(self.replies.wait(request_digest(request)).await);
+        }
+        let Some(waiter) = waiter else {
+            if let Err(e) = self.sender.send(msg).await {
+                error!("send message to pool err: {}", e)
+            }
+            return Ok(None);
+        };
+        // clients are told to back off rather than queue behind a full pool
+        if let Err(e) = self.sender.try_send(msg) {
+            let total = self.metrics.drop_message(DropReason::QueueFull);
+            warn!("send request to pool err: {}. total:{}", e, total);
+            return Err(ConsensusError::Busy());
+        }
+        match time::timeout(self.reply_timeout, waiter).await {
+            Ok(Ok(reply)) => Ok(Some(reply)),
+            // the pool dropped the waiter without executing the request
+            Ok(Err(_)) => Err(ConsensusError::Busy()),
+            Err(_) => Err(ConsensusError::ReplyTimeout()),
+        }
+    }
+
+    /// with tls, protocol messages have to come from the replica named by the peer certificate
+    fn authenticate(
+        &self,
+        certs: Option<&[Certificate]>,
+        msg: &Message,
+    ) -> Result<(), ConsensusError> {
+        if !self.tls || matches!(msg.payload, Some(Payload::Request(_))) {
+            return Ok(());
+        }
+        match certs {
+            Some(certs) if tls::is_node(certs, msg.id) => Ok(()),
+            _ => {
+                let total = self.metrics.reject(RejectReason::UnknownSender);
+                warn!(
+                    "[REJECT] {} message from node{}, not the peer certificate. view:{}, sequence:{}, total:{}",
+                    RejectReason::UnknownSender, msg.id, msg.view, msg.seq, total
+                );
+                Err(ConsensusError::UnauthenticatedPeer(msg.id))
+            }
+        }
+    }
+
+    /// with tls, state is only served to replicas presenting the certificate of a member
+    fn authenticate_replica(&self, certs: Option<&[Certificate]>) -> Result<(), ConsensusError> {
+        if !self.tls {
+            return Ok(());
+        }
+        let member = certs.is_some_and(|certs| {
+            self.member
+                .members()
+                .keys()
+                .any(|id| tls::is_node(certs, *id as u64))
+        });
+        if member {
+            return Ok(());
+        }
+        let total = self.metrics.reject(RejectReason::UnknownSender);
+        warn!(
+            "[REJECT] {} state transfer, no member certificate. total:{}",
+            RejectReason::UnknownSender,
+            total
+        );
+        Err(ConsensusError::UnauthenticatedReplica())
+    }
+
+    async fn respond(&self, certs: Option<&[Certificate]>, msg: Message) -> MessageResponse {
+        let result = match self.authenticate(certs, &msg) {
+            Ok(()) => self.request(msg).await,
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(reply) => MessageResponse {
+                message: String::from("success"),
+                reply,
+                busy: false,
+            },
+            Err(err) => MessageResponse {
+                message: err.to_string(),
+                reply: None,
+                busy: matches!(err, ConsensusError::Busy()),
+            },
         }
-        Ok(())
     }
 }
 
 #[tonic::async_trait]
 impl Pbft for Server {
+    type FetchCheckpointStream =
+        Pin<Box<dyn Stream<Item = Result<SnapshotChunk, tonic::Status>> + Send>>;
+
+    type MessageStreamStream =
+        Pin<Box<dyn Stream<Item = Result<MessageResponse, tonic::Status>> + Send>>;
+
     async fn send_message(
         &self,
         request: tonic::Request<Message>,
     ) -> std::result::Result<tonic::Response<MessageResponse>, tonic::Status> {
-        match self.request(request.into_inner()).await {
-            Ok(_) => {}
-            Err(err) => {
-                let reply = MessageResponse {
-                    message: err.to_string(),
+        let certs = request.peer_certs();
+        let resp = self
+            .respond(certs.as_deref().map(Vec::as_slice), request.into_inner())
+            .await;
+        Ok(Response::new(resp))
+    }
+
+    async fn message_stream(
+        &self,
+        request: tonic::Request<tonic::Streaming<Message>>,
+    ) -> std::result::Result<tonic::Response<Self::MessageStreamStream>, tonic::Status> {
+        let certs = request.peer_certs();
+        let mut inbound = request.into_inner();
+        let server = self.clone();
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        // messages of a stream are handled in order, one response each
+        tokio::spawn(async move {
+            loop {
+                let msg = match inbound.message().await {
+                    Ok(Some(msg)) => msg,
+                    Ok(None) => break,
+                    Err(status) => {
+                        debug!("message stream closed: {}", status);
+                        break;
+                    }
                 };
-                return Ok(Response::new(reply));
+                let resp = server
+                    .respond(certs.as_deref().map(Vec::as_slice), msg)
+                    .await;
+                if tx.send(Ok(resp)).await.is_err() {
+                    break;
+                }
             }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn get_status(
+        &self,
+        _request: tonic::Request<NodeStatusRequest>,
+    ) -> std::result::Result<tonic::Response<NodeStatus>, tonic::Status> {
+        Ok(Response::new(NodeStatus {
+            node: self.local as u64,
+            view: self.metrics.view(),
+            stable_checkpoint: self.metrics.stable_checkpoint(),
+        }))
+    }
+
+    async fn get_metrics(
+        &self,
+        _request: tonic::Request<MetricsRequest>,
+    ) -> std::result::Result<tonic::Response<MetricsReport>, tonic::Status> {
+        let dropped = self.peers.dropped().await;
+        let mut peers: Vec<PeerMetrics> = self
+            .peers
+            .states()
+            .await
+            .into_iter()
+            .map(|(id, state)| PeerMetrics {
+                node: id as u64,
+                state: state.as_str().to_string(),
+                failures: match state {
+                    ConnectionState::Failed { failures, .. } => failures,
+                    _ => 0,
+                },
+                dropped: dropped.get(&id).copied().unwrap_or_default(),
+            })
+            .collect();
+        peers.sort_unstable_by_key(|peer| peer.node);
+        Ok(Response::new(MetricsReport {
+            node: self.local as u64,
+            view: self.metrics.view(),
+            stable_checkpoint: self.metrics.stable_checkpoint(),
+            rejected: self
+                .metrics
+                .rejected_all()
+                .into_iter()
+                .map(|(reason, count)| (reason.to_string(), count))
+                .collect(),
+            dropped: self
+                .metrics
+                .dropped_all()
+                .into_iter()
+                .map(|(reason, count)| (reason.to_string(), count))
+                .collect(),
+            peers,
+        }))
+    }
+
+    async fn fetch_checkpoint(
+        &self,
+        request: tonic::Request<FetchCheckpointRequest>,
+    ) -> std::result::Result<tonic::Response<Self::FetchCheckpointStream>, tonic::Status> {
+        let certs = request.peer_certs();
+        self.authenticate_replica(certs.as_deref().map(Vec::as_slice))
+            .map_err(|e| tonic::Status::unauthenticated(e.to_string()))?;
+        let Some(chunks) = self.store.chunks().await else {
+            return Err(tonic::Status::not_found(
+                "no snapshot of the stable checkpoint",
+            ));
         };
+        Ok(Response::new(Box::pin(tokio_stream::iter(
+            chunks.into_iter().map(Ok),
+        ))))
+    }
 
-        Ok(Response::new(MessageResponse {
-            message: String::from("success"),
+    async fn fetch_committed_range(
+        &self,
+        request: tonic::Request<CommittedRangeRequest>,
+    ) -> std::result::Result<tonic::Response<CommittedRange>, tonic::Status> {
+        let certs = request.peer_certs();
+        self.authenticate_replica(certs.as_deref().map(Vec::as_slice))
+            .map_err(|e| tonic::Status::unauthenticated(e.to_string()))?;
+        let range = request.into_inner();
+        Ok(Response::new(CommittedRange {
+            entries: self.store.committed(range.from, range.to).await,
         }))
     }
 }
 
-pub async fn run(member: Arc<Members>, address: String) -> Result<(), ConsensusError> {
+pub async fn run<S: StateMachine>(
+    member: Arc<Members>,
+    address: String,
+    options: Options,
+    state_machine: S,
+) -> Result<(), ConsensusError> {
     let addr = address.parse()?;
 
+    quorum::validate(member.members().len())?;
+    // the window has to slide past a checkpoint to ever become free again
+    if options.window as u64 <= options.checkpoint_interval {
+        return Err(ConsensusError::WindowTooSmall(
+            options.window,
+            options.checkpoint_interval,
+        ));
+    }
+    // view changes and checkpoints would go unauthenticated
+    if options.authenticators && options.signing_key.is_none() {
+        return Err(ConsensusError::MacWithoutSigningKey());
+    }
+
     let (tx_req, rv_req) = mpsc::channel(1024); // request
 
     let (tx_event, rv_event) = mpsc::channel(1024); // event
 
-    let server = Server { sender: tx_req };
+    let (tx_checkpoint, rv_checkpoint) = mpsc::unbounded_channel(); // checkpoint
+
+    let replies = Arc::new(Replies::default());
+    let store = Arc::new(Store::default());
+    let mut peers = Peers::new(options.send_queue, options.transport);
+    let mut transport_server = TransportServer::builder();
+    if let Some(ref tls) = options.tls {
+        peers = peers.with_tls(tls.clone());
+        transport_server = transport_server.tls_config(tls::server_config(tls)?)?;
+    }
+    let peers = Arc::new(peers);
+
+    let metrics = Arc::new(Metrics::default());
+
+    let server = Server {
+        local: member.local_id(),
+        member: member.clone(),
+        metrics: metrics.clone(),
+        peers: peers.clone(),
+        sender: tx_req.clone(),
+        replies: replies.clone(),
+        store: store.clone(),
+        reply_timeout: options.reply_timeout,
+        tls: options.tls.is_some(),
+    };
+
+    let mut request_handler = RequestHandler::new(
+        member.clone(),
+        rv_req,
+        rv_checkpoint,
+        tx_event,
+        &options,
+        metrics.clone(),
+        replies.clone(),
+        store.clone(),
+    );
 
-    let mut request_handler = RequestHandler::new(member.clone(), rv_req, 10, tx_event);
+    let mut event_handler = EventHandler::new(
+        member.clone(),
+        rv_event,
+        tx_checkpoint,
+        &options,
+        state_machine,
+        replies,
+        store,
+        peers.clone(),
+    );
 
-    let mut event_handler = EventHandler::new(member.clone(), rv_event);
+    // restart from the last snapshot and the log written after it
+    if let Some(ref dir) = options.data_dir {
+        if let Some(snapshot) = snapshot::load(dir)? {
+            event_handler.restore(snapshot).await?;
+        }
+        let (wal, records) = Wal::open(&dir.join("pool.wal"), options.wal_sync)?;
+        request_handler.recover(wal, records).await;
+    } else {
+        warn!("data dir is not configured, protocol state is lost on restart");
+    }
+
+    // catch up with the view the others moved to while this node was down
+    let statuses = recovery::peer_status(&peers, member.local_id(), member.members()).await;
+    let n = member.members().len();
+    if let Some(view) = recovery::current_view(&statuses, n) {
+        request_handler.join_view(view as usize).await;
+    }
+    if let Some(seq) = recovery::stable_checkpoint(&statuses, n) {
+        if seq > event_handler.commited_seq() {
+            event_handler.transfer(seq).await;
+        }
+    }
+
+    if options.signing_key.is_none() {
+        warn!("message signing is not configured, sender ids are not authenticated");
+    }
 
     let task_req = tokio::spawn(async move {
         debug!("request handler starting...");
@@ -73,7 +458,7 @@ pub async fn run(member: Arc<Members>, address: String) -> Result<(), ConsensusE
 
     let task_server = tokio::spawn(async move {
         info!("PBFT server listening on {}...", addr);
-        if let Err(e) = TransportServer::builder()
+        if let Err(e) = transport_server
             .add_service(PbftServer::new(server))
             .serve(addr)
             .await
@@ -0,0 +1,34 @@
+use crate::error::ConsensusError;
+use crate::message::Snapshot;
+use prost::Message as _;
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+};
+
+const SNAPSHOT_FILE: &str = "checkpoint.snap";
+
+/// replace the persisted snapshot under `dir`, a crash leaves either the old or the new one
+pub fn save(dir: &Path, snapshot: &Snapshot) -> Result<(), ConsensusError> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(SNAPSHOT_FILE);
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(&snapshot.encode_to_vec())?;
+    file.sync_all()?;
+    fs::rename(&tmp, &path)?;
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// the latest snapshot persisted under `dir`, if any
+pub fn load(dir: &Path) -> Result<Option<Snapshot>, ConsensusError> {
+    match fs::read(dir.join(SNAPSHOT_FILE)) {
+        Ok(bytes) => Ok(Some(Snapshot::decode(bytes.as_slice())?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
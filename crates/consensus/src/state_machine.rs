@@ -0,0 +1,55 @@
+use crate::error::ConsensusError;
+use sha2::{Digest, Sha256};
+
+/// the replicated application. requests are executed in commit order on every replica,
+/// so `execute` must be deterministic
+pub trait StateMachine: Send + 'static {
+    /// execute the request committed at `seq` and return its result
+    fn execute(&mut self, seq: u64, payload: &[u8]) -> Vec<u8>;
+    /// serialize the whole state
+    fn snapshot(&self) -> Vec<u8>;
+    /// replace the state with a snapshot taken by `snapshot`
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), ConsensusError>;
+    /// digest of the current state, compared between replicas in checkpoints
+    fn digest(&self) -> String;
+    /// the digest the state would have once `snapshot` is restored. a snapshot from
+    /// another replica is checked with it before it replaces the state
+    fn snapshot_digest(snapshot: &[u8]) -> Result<String, ConsensusError>
+    where
+        Self: Sized;
+}
+
+/// a state machine that only chains the digests of the executed requests
+#[derive(Default)]
+pub struct HashChain {
+    digest: String,
+}
+
+impl StateMachine for HashChain {
+    fn execute(&mut self, seq: u64, payload: &[u8]) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(self.digest.as_bytes());
+        hasher.update(seq.to_be_bytes());
+        hasher.update(payload);
+        self.digest = format!("{:x}", hasher.finalize());
+        self.digest.clone().into_bytes()
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        self.digest.clone().into_bytes()
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), ConsensusError> {
+        self.digest = Self::snapshot_digest(snapshot)?;
+        Ok(())
+    }
+
+    fn digest(&self) -> String {
+        self.digest.clone()
+    }
+
+    fn snapshot_digest(snapshot: &[u8]) -> Result<String, ConsensusError> {
+        String::from_utf8(snapshot.to_vec())
+            .map_err(|e| ConsensusError::StateMachineError(e.to_string()))
+    }
+}
@@ -0,0 +1,96 @@
+use crate::error::ConsensusError;
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyUsagePurpose};
+use rustls_pki_types::{CertificateDer, ServerName};
+use std::{fs, path::Path, path::PathBuf};
+use tonic::transport::{self, ClientTlsConfig, Identity, ServerTlsConfig};
+use webpki::EndEntityCert;
+
+/// pem files of the cluster ca and of the certificate of this node
+#[derive(Clone, Debug)]
+pub struct TlsOptions {
+    pub ca: PathBuf,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// the dns name every certificate of node `id` is issued for
+pub fn node_name(id: usize) -> String {
+    format!("node{}", id)
+}
+
+/// replicas have to present a certificate signed by the ca, clients may connect without one
+pub fn server_config(options: &TlsOptions) -> Result<ServerTlsConfig, ConsensusError> {
+    Ok(ServerTlsConfig::new()
+        .identity(identity(options)?)
+        .client_ca_root(transport::Certificate::from_pem(fs::read(&options.ca)?))
+        .client_auth_optional(true))
+}
+
+/// connect to node `id`, whose certificate must be issued for its node name
+pub fn client_config(options: &TlsOptions, id: usize) -> Result<ClientTlsConfig, ConsensusError> {
+    Ok(anonymous_config(&options.ca, id)?.identity(identity(options)?))
+}
+
+/// connect to node `id` without presenting a certificate. clients connect this way, only
+/// replicas hold one
+pub fn anonymous_config(ca: &Path, id: usize) -> Result<ClientTlsConfig, ConsensusError> {
+    Ok(ClientTlsConfig::new()
+        .ca_certificate(transport::Certificate::from_pem(fs::read(ca)?))
+        .domain_name(node_name(id)))
+}
+
+fn identity(options: &TlsOptions) -> Result<Identity, ConsensusError> {
+    Ok(Identity::from_pem(
+        fs::read(&options.cert)?,
+        fs::read(&options.key)?,
+    ))
+}
+
+/// the peer presented a certificate issued for node `id`. the handshake already checked it
+/// against the ca
+pub(crate) fn is_node(certs: &[transport::Certificate], id: u64) -> bool {
+    let Some(cert) = certs.first() else {
+        return false;
+    };
+    let der = CertificateDer::from(cert.get_ref());
+    let Ok(cert) = EndEntityCert::try_from(&der) else {
+        return false;
+    };
+    let Ok(name) = ServerName::try_from(node_name(id as usize)) else {
+        return false;
+    };
+    cert.verify_is_valid_for_subject_name(&name).is_ok()
+}
+
+/// generate a ca in `dir` and a certificate signed by it for every node in `ids`
+pub fn write_certificates(dir: &Path, ids: &[usize]) -> Result<(), ConsensusError> {
+    fs::create_dir_all(dir)?;
+    let mut params = CertificateParams::default();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "pbft ca");
+    let ca = Certificate::from_params(params).map_err(cert_err)?;
+    fs::write(dir.join("ca.pem"), ca.serialize_pem().map_err(cert_err)?)?;
+
+    for id in ids {
+        let name = node_name(*id);
+        let mut params = CertificateParams::new(vec![name.clone()]);
+        params.distinguished_name.push(DnType::CommonName, &name);
+        let cert = Certificate::from_params(params).map_err(cert_err)?;
+        fs::write(
+            dir.join(format!("{}.pem", name)),
+            cert.serialize_pem_with_signer(&ca).map_err(cert_err)?,
+        )?;
+        fs::write(
+            dir.join(format!("{}.key", name)),
+            cert.serialize_private_key_pem(),
+        )?;
+    }
+    Ok(())
+}
+
+fn cert_err(err: rcgen::Error) -> ConsensusError {
+    ConsensusError::CertificateError(err.to_string())
+}
@@ -0,0 +1,294 @@
+use crate::checkpoint;
+use crate::crypto;
+use crate::digest::{batch_digest, checkpoint_digest};
+use crate::error::ConsensusError;
+use crate::members::Membership;
+use crate::message::{message::Payload, CommittedEntry, Message, Snapshot, SnapshotChunk};
+use crate::peers::Peers;
+use crate::quorum;
+use crate::state_machine::StateMachine;
+use prost::Message as _;
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+use tokio::{sync::Mutex, time};
+use tracing::{debug, warn};
+
+// snapshots are streamed in chunks of this many bytes
+const CHUNK_SIZE: usize = 1024 * 1024;
+// the largest snapshot accepted from another replica
+const MAX_SNAPSHOT_BYTES: u64 = 1024 * 1024 * 1024;
+// a replica that does not serve a fetch in time is skipped
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// a proven checkpoint fetched from another replica and the requests committed after it
+pub struct Transferred {
+    pub node: usize,
+    /// none if the replica was not ahead of this one
+    pub snapshot: Option<Snapshot>,
+    pub committed: Vec<Message>,
+}
+
+/// what a replica serves to the lagging ones: its stable checkpoint with proof and snapshot,
+/// and the requests committed after it
+#[derive(Default)]
+pub struct Store {
+    proof: Mutex<Vec<Message>>,
+    snapshots: Mutex<BTreeMap<u64, Snapshot>>,
+    committed: Mutex<BTreeMap<u64, CommittedEntry>>,
+}
+
+impl Store {
+    /// the checkpoint at `seq` became stable, forget everything before it
+    pub async fn stable(&self, seq: u64, proof: Vec<Message>) {
+        *self.proof.lock().await = proof;
+        self.snapshots.lock().await.retain(|s, _| *s >= seq);
+        self.committed.lock().await.retain(|s, _| *s > seq);
+    }
+
+    pub async fn snapshot(&self, snapshot: Snapshot) {
+        let stable = stable_seq(&self.proof.lock().await);
+        if snapshot.seq >= stable {
+            self.snapshots.lock().await.insert(snapshot.seq, snapshot);
+        }
+    }
+
+    pub async fn commit(&self, entry: CommittedEntry) {
+        if let Some(ref m) = entry.pre_prepare {
+            self.committed.lock().await.insert(m.seq, entry);
+        }
+    }
+
+    /// the snapshot of the stable checkpoint split into chunks, none if this replica
+    /// did not reach it itself
+    pub async fn chunks(&self) -> Option<Vec<SnapshotChunk>> {
+        let proof = self.proof.lock().await.clone();
+        let seq = stable_seq(&proof);
+        let bytes = self.snapshots.lock().await.get(&seq)?.encode_to_vec();
+        let total = bytes.len() as u64;
+        let mut chunks: Vec<SnapshotChunk> = bytes
+            .chunks(CHUNK_SIZE)
+            .enumerate()
+            .map(|(i, data)| SnapshotChunk {
+                checkpoints: vec![],
+                offset: (i * CHUNK_SIZE) as u64,
+                total,
+                data: data.to_vec(),
+            })
+            .collect();
+        if chunks.is_empty() {
+            chunks.push(SnapshotChunk {
+                checkpoints: vec![],
+                offset: 0,
+                total,
+                data: vec![],
+            });
+        }
+        chunks[0].checkpoints = proof;
+        Some(chunks)
+    }
+
+    pub async fn committed(&self, from: u64, to: u64) -> Vec<CommittedEntry> {
+        let to = if to == 0 { u64::MAX } else { to };
+        self.committed
+            .lock()
+            .await
+            .range(from..=to)
+            .map(|(_, entry)| entry.clone())
+            .collect()
+    }
+}
+
+fn stable_seq(proof: &[Message]) -> u64 {
+    proof
+        .iter()
+        .find_map(|m| match m.payload {
+            Some(Payload::Checkpoint(ref cp)) => Some(cp.seq),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+/// fetch a proven checkpoint at `seq` or later from one of the others, and what it
+/// committed after the checkpoint or after `commited`, whichever is later
+pub async fn fetch<T: Membership, S: StateMachine>(
+    peers: &Peers,
+    members: &T,
+    seq: u64,
+    commited: u64,
+    verify_checkpoints: bool,
+    verify_commits: bool,
+) -> Option<Transferred> {
+    let local = members.local_id();
+    for (id, addr) in members.members() {
+        if id == local {
+            continue;
+        }
+        let fetched = time::timeout(
+            FETCH_TIMEOUT,
+            fetch_checkpoint::<T, S>(peers, id, &addr, members, verify_checkpoints),
+        )
+        .await
+        .unwrap_or(Err(ConsensusError::InvalidStateTransfer(String::from(
+            "fetch checkpoint timed out",
+        ))));
+        let snapshot = match fetched {
+            Ok(snapshot) if snapshot.seq >= seq => snapshot,
+            Ok(snapshot) => {
+                debug!(
+                    "[TRANSFER] node{} is stable at sequence:{} only",
+                    id, snapshot.seq
+                );
+                continue;
+            }
+            Err(err) => {
+                warn!("[TRANSFER] fetch checkpoint from node{} err: {}", id, err);
+                continue;
+            }
+        };
+
+        let from = snapshot.seq.max(commited) + 1;
+        let mut committed = Vec::new();
+        match time::timeout(
+            FETCH_TIMEOUT,
+            peers.fetch_committed_range(id, &addr, from, 0),
+        )
+        .await
+        {
+            Ok(Ok(range)) => {
+                for entry in range.entries {
+                    match verify_committed(entry, members, verify_commits) {
+                        Some(m) => committed.push(m),
+                        None => warn!("[TRANSFER] node{} sent an unproven entry", id),
+                    }
+                }
+            }
+            Ok(Err(err)) => warn!("[TRANSFER] fetch committed from node{} err: {}", id, err),
+            Err(_) => warn!("[TRANSFER] fetch committed from node{} timed out", id),
+        }
+        return Some(Transferred {
+            node: id,
+            snapshot: (snapshot.seq > commited).then_some(snapshot),
+            committed,
+        });
+    }
+    None
+}
+
+/// fetch the stable checkpoint of replica `id`, check it against its proof and its digest
+pub async fn fetch_checkpoint<T: Membership, S: StateMachine>(
+    peers: &Peers,
+    id: usize,
+    addr: &str,
+    members: &T,
+    verify_signatures: bool,
+) -> Result<Snapshot, ConsensusError> {
+    let mut stream = peers.fetch_checkpoint(id, addr).await?;
+    let mut proof = Vec::new();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = stream.message().await? {
+        if chunk.offset == 0 {
+            proof = chunk.checkpoints;
+        }
+        if chunk.offset != bytes.len() as u64 {
+            return Err(ConsensusError::InvalidStateTransfer(String::from(
+                "snapshot chunks out of order",
+            )));
+        }
+        let size = bytes.len() as u64 + chunk.data.len() as u64;
+        if chunk.total > MAX_SNAPSHOT_BYTES || size > chunk.total {
+            return Err(ConsensusError::InvalidStateTransfer(format!(
+                "snapshot of {} bytes, at most {} accepted",
+                chunk.total.max(size),
+                MAX_SNAPSHOT_BYTES
+            )));
+        }
+        bytes.extend_from_slice(&chunk.data);
+        if size == chunk.total {
+            break;
+        }
+    }
+    let snapshot = Snapshot::decode(bytes.as_slice())?;
+    if !checkpoint_proven(&snapshot, &proof, members, verify_signatures) {
+        return Err(ConsensusError::InvalidStateTransfer(format!(
+            "snapshot at sequence {} is not proven by 2f+1 checkpoints",
+            snapshot.seq
+        )));
+    }
+    if !snapshot_matches::<S>(&snapshot) {
+        return Err(ConsensusError::SnapshotMismatch(snapshot.seq));
+    }
+    Ok(snapshot)
+}
+
+/// the state and the replies of `snapshot` have the digest of its checkpoint
+pub fn snapshot_matches<S: StateMachine>(snapshot: &Snapshot) -> bool {
+    S::snapshot_digest(&snapshot.state)
+        .is_ok_and(|state| checkpoint_digest(&state, &snapshot.replies) == snapshot.digest)
+}
+
+/// 2f+1 members signed a checkpoint with the digest of the snapshot
+pub fn checkpoint_proven<T: Membership>(
+    snapshot: &Snapshot,
+    proof: &[Message],
+    members: &T,
+    verify_signatures: bool,
+) -> bool {
+    let n = members.members().len();
+    let valid: Vec<Message> = proof
+        .iter()
+        .filter(|m| authentic(m, members, verify_signatures))
+        .cloned()
+        .collect();
+    checkpoint::is_proven(
+        snapshot.seq,
+        &snapshot.digest,
+        &valid,
+        quorum::commit_quorum(n),
+    )
+}
+
+/// the pre-prepare of `entry` if it matches its digest and 2f+1 members committed it
+pub fn verify_committed<T: Membership>(
+    entry: CommittedEntry,
+    members: &T,
+    verify_signatures: bool,
+) -> Option<Message> {
+    let pre_prepare = entry.pre_prepare?;
+    match pre_prepare.payload {
+        Some(Payload::PrePrepare(ref p)) if batch_digest(&p.requests) == pre_prepare.digest => {}
+        _ => return None,
+    }
+    let committers: HashSet<u64> = entry
+        .commits
+        .iter()
+        .filter(|m| {
+            matches!(m.payload, Some(Payload::Commit(_)))
+                && m.view == pre_prepare.view
+                && m.seq == pre_prepare.seq
+                && m.digest == pre_prepare.digest
+                && authentic(m, members, verify_signatures)
+        })
+        .map(|m| m.id)
+        .collect();
+    if committers.len() < quorum::commit_quorum(members.members().len()) {
+        return None;
+    }
+    Some(pre_prepare)
+}
+
+fn authentic<T: Membership>(m: &Message, members: &T, verify_signatures: bool) -> bool {
+    if !members.members().contains_key(&(m.id as usize)) {
+        return false;
+    }
+    if !verify_signatures {
+        return true;
+    }
+    if crypto::has_authenticator(m) {
+        if let Some(key) = members.mac_key(m.id as usize) {
+            return crypto::verify_mac(m, members.local_id(), &key);
+        }
+    }
+    members
+        .public_key(m.id as usize)
+        .is_some_and(|key| crypto::verify(m, &key))
+}
@@ -0,0 +1,69 @@
+use crate::digest::batch_digest;
+use crate::message::{message::Payload, Message, PrePrepare, PreparedCert};
+use std::collections::{BTreeMap, HashMap};
+
+/// primary of a view: `view mod n` over the sorted member ids
+pub fn primary(view: usize, members: &HashMap<usize, String>) -> Option<usize> {
+    let mut ids: Vec<usize> = members.keys().copied().collect();
+    if ids.is_empty() {
+        return None;
+    }
+    ids.sort_unstable();
+    Some(ids[view % ids.len()])
+}
+
+/// the highest stable checkpoint claimed by a set of view-change messages (min-s)
+pub fn min_seq(view_changes: &[Message]) -> u64 {
+    view_changes
+        .iter()
+        .filter_map(|m| match m.payload {
+            Some(Payload::ViewChange(ref vc)) => Some(vc.stable_checkpoint),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// pre-prepare messages the new primary has to re-issue in `new_view`.
+///
+/// every seq between min-s and max-s gets the prepared certificate with the highest view,
+/// or a null request if none of the view-change messages prepared it
+pub fn pre_prepares(new_view: u64, primary: u64, view_changes: &[Message]) -> Vec<Message> {
+    let min_s = min_seq(view_changes);
+
+    let mut certs: BTreeMap<u64, &PreparedCert> = BTreeMap::new();
+    for m in view_changes {
+        if let Some(Payload::ViewChange(ref vc)) = m.payload {
+            for cert in vc.prepared.iter().filter(|c| c.seq > min_s) {
+                match certs.get(&cert.seq) {
+                    Some(prev) if prev.view >= cert.view => {}
+                    _ => {
+                        certs.insert(cert.seq, cert);
+                    }
+                }
+            }
+        }
+    }
+
+    let max_s = certs.keys().last().copied().unwrap_or(min_s);
+
+    (min_s + 1..=max_s)
+        .map(|seq| {
+            let (digest, requests) = match certs.get(&seq) {
+                Some(cert) => (cert.digest.clone(), cert.requests.clone()),
+                None => (batch_digest(&[]), vec![]),
+            };
+            Message {
+                view: new_view,
+                seq,
+                id: primary,
+                digest,
+                payload: Some(Payload::PrePrepare(PrePrepare {
+                    signature: vec![],
+                    requests,
+                    authenticator: BTreeMap::new(),
+                })),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,156 @@
+use crate::error::ConsensusError;
+use crate::message::{wal_record::Record, Message, WalRecord};
+use prost::Message as _;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+// every record is framed as [len: u32][crc32 of the record: u32][record], little endian
+const HEADER_LEN: usize = 8;
+// a longer length can only come from a corrupt header
+const MAX_RECORD_LEN: usize = 64 * 1024 * 1024;
+
+/// when appended records are flushed to disk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// fsync every record before it is acted upon
+    Always,
+    /// fsync at most once per interval, a crash may lose the records of the last interval
+    Interval(Duration),
+    /// leave flushing to the os
+    Never,
+}
+
+/// append-only, checksummed log of the protocol state of the pool
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+    sync: SyncPolicy,
+    last_sync: Instant,
+}
+
+impl Wal {
+    /// open the log at `path` and read back its records.
+    /// a torn or corrupt tail left by a crash is cut off
+    pub fn open(path: &Path, sync: SyncPolicy) -> Result<(Self, Vec<WalRecord>), ConsensusError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let (records, valid_len) = match File::open(path) {
+            Ok(file) => read_records(file)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (Vec::new(), 0),
+            Err(e) => return Err(e.into()),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() > valid_len {
+            warn!(
+                "[WAL] {} has a corrupt tail, truncate to {} bytes",
+                path.display(),
+                valid_len
+            );
+            file.set_len(valid_len)?;
+            file.sync_all()?;
+        }
+        Ok((
+            Self {
+                path: path.to_path_buf(),
+                file,
+                sync,
+                last_sync: Instant::now(),
+            },
+            records,
+        ))
+    }
+
+    pub fn append(&mut self, record: &WalRecord) -> Result<(), ConsensusError> {
+        self.file.write_all(&frame(record))?;
+        self.sync()
+    }
+
+    pub fn append_message(&mut self, m: &Message) -> Result<(), ConsensusError> {
+        self.append(&WalRecord {
+            record: Some(Record::Message(m.clone())),
+        })
+    }
+
+    pub fn append_view(&mut self, view: u64) -> Result<(), ConsensusError> {
+        self.append(&WalRecord {
+            record: Some(Record::View(view)),
+        })
+    }
+
+    /// replace the whole log with `records`, used to drop what a stable checkpoint made obsolete
+    pub fn compact(&mut self, records: &[WalRecord]) -> Result<(), ConsensusError> {
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        for record in records {
+            file.write_all(&frame(record))?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        if let Some(dir) = self.path.parent() {
+            // make the rename durable
+            if let Ok(dir) = File::open(dir) {
+                let _ = dir.sync_all();
+            }
+        }
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), ConsensusError> {
+        match self.sync {
+            SyncPolicy::Always => self.file.sync_data()?,
+            SyncPolicy::Interval(interval) => {
+                if self.last_sync.elapsed() >= interval {
+                    self.file.sync_data()?;
+                    self.last_sync = Instant::now();
+                }
+            }
+            SyncPolicy::Never => {}
+        }
+        Ok(())
+    }
+}
+
+fn frame(record: &WalRecord) -> Vec<u8> {
+    let body = record.encode_to_vec();
+    let mut buf = Vec::with_capacity(HEADER_LEN + body.len());
+    buf.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+    buf.extend_from_slice(&body);
+    buf
+}
+
+/// the records up to the first torn or corrupt one, and the length they take
+fn read_records(file: File) -> Result<(Vec<WalRecord>, u64), ConsensusError> {
+    let mut reader = BufReader::new(file);
+    let mut records = Vec::new();
+    let mut valid_len = 0u64;
+    loop {
+        let mut header = [0u8; HEADER_LEN];
+        if reader.read_exact(&mut header).is_err() {
+            break;
+        }
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if len > MAX_RECORD_LEN {
+            break;
+        }
+        let mut body = vec![0u8; len];
+        if reader.read_exact(&mut body).is_err() || crc32fast::hash(&body) != crc {
+            break;
+        }
+        match WalRecord::decode(body.as_slice()) {
+            Ok(record) => records.push(record),
+            Err(_) => break,
+        }
+        valid_len += (HEADER_LEN + len) as u64;
+    }
+    Ok((records, valid_len))
+}